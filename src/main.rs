@@ -2,19 +2,99 @@
 extern crate minifb;
 
 use std::env;
+use std::process;
 
 mod nes;
-use nes::NES;
+use nes::{NES, ExecutionMode, CpuVariant, FileTrace};
 
-fn main() {
-    let mut nes = NES::new();
+//Value of a --name=value argument
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let prefix = format!("--{}=", name);
+
+    args.iter().find(|arg| arg.starts_with(&prefix)).map(|arg| &arg[prefix.len()..])
+}
 
+fn main() {
     let args: Vec<String> = env::args().collect();
+    let roms: Vec<&String> = args.iter().skip(1).filter(|arg| !arg.starts_with("--")).collect();
+
+    //Headless CPU check against nestest.nes, see README
+    if args.iter().any(|arg| arg == "--nestest") {
+        if roms.len() == 0 {
+            println!("usage: nes_emu_rust --nestest <nestest.nes> [--golden=<nestest.log>] [--log=<output>]");
+            process::exit(2);
+        }
+
+        let passed = nes::nestest::run(roms[0], arg_value(&args, "golden"), arg_value(&args, "log"));
+        process::exit(if passed { 0 } else { 1 });
+    }
+
+    //Per-opcode JSON test suites for the cpu, see README
+    if let Some(dir) = arg_value(&args, "single-step") {
+        //The NES's cpu unless asked for a stock 6502 with decimal mode
+        let variant = if args.iter().any(|arg| arg == "--nmos-6502") {
+            CpuVariant::Nmos6502
+        } else {
+            CpuVariant::Ricoh2A03
+        };
+
+        let passed = nes::single_step::run(dir, variant);
+        process::exit(if passed { 0 } else { 1 });
+    }
+
+    //Klaus Dormann's functional and interrupt tests, see README
+    if let Some(dir) = arg_value(&args, "klaus") {
+        let success = |name, default| arg_value(&args, name)
+            .and_then(|addr| u16::from_str_radix(addr.trim_start_matches('$'), 16).ok())
+            .unwrap_or(default);
 
-    if args.len() > 1 {
-        let rom_name = &args[1];
+        let passed = nes::klaus::run(dir, success("functional-success", nes::klaus::FUNCTIONAL_SUCCESS),
+                                     success("interrupt-success", nes::klaus::INTERRUPT_SUCCESS));
+        process::exit(if passed { 0 } else { 1 });
+    }
+
+    //Times the cpu on its own, see README
+    if args.iter().any(|arg| arg == "--bench" || arg.starts_with("--bench=")) {
+        let instructions = arg_value(&args, "instructions")
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(50_000_000);
+
+        let passed = nes::bench::run(arg_value(&args, "bench"), instructions);
+        process::exit(if passed { 0 } else { 1 });
+    }
+
+    //Slower, but every cpu bus access lands on the right ppu dot
+    let mode = if args.iter().any(|arg| arg == "--cycle-stepped") {
+        ExecutionMode::CycleStepped
+    } else {
+        ExecutionMode::InstructionStepped
+    };
+
+    let mut nes = NES::with_cpu_mode(mode);
+
+    if roms.len() > 0 {
+        let rom_name = roms[0];
+
+        if let Err(why) = nes.insert_cart(rom_name) {
+            println!("couldn't load {}: {}", rom_name, why);
+            process::exit(1);
+        }
+    }
+
+    //Logs every instruction, this slows things down a lot
+    if let Some(path) = arg_value(&args, "trace") {
+        match FileTrace::new(path) {
+            Ok(trace) => nes.set_trace_sink(Some(Box::new(trace))),
+            Err(why) => println!("couldn't open trace file: {}", why),
+        }
+    }
 
-        nes.insert_cart(rom_name);
+    //Prints the instructions leading up to the cpu stopping on an error
+    if let Some(count) = arg_value(&args, "history") {
+        match count.parse() {
+            Ok(count) => nes.keep_history(count),
+            Err(_) => println!("--history needs a number of instructions, not {}", count),
+        }
     }
 
     nes.hard_restart();
@@ -1,11 +1,75 @@
+use super::irq::*;
+
+//Frame counter lengths in cpu cycles (NTSC)
+//https://wiki.nesdev.com/w/index.php/APU_Frame_Counter
+const FOUR_STEP_IRQ: u32 = 29829;
+const FOUR_STEP_LENGTH: u32 = 29830;
+const FIVE_STEP_LENGTH: u32 = 37282;
+
 pub struct NESApu {
+    irq: IrqLine,
 
+    frame_counter: u32,
+    five_step_mode: bool,
+    irq_inhibit: bool,
 }
 
 impl NESApu {
-    pub fn new() -> NESApu {
+    pub fn new(irq: IrqLine) -> NESApu {
         NESApu {
-            
+            irq: irq,
+
+            frame_counter: 0,
+            five_step_mode: false,
+            irq_inhibit: false,
+        }
+    }
+
+    //Called once per cpu cycle
+    pub fn tick(&mut self) {
+        self.frame_counter += 1;
+
+        if self.five_step_mode {
+            if self.frame_counter >= FIVE_STEP_LENGTH {
+                self.frame_counter = 0;
+            }
+        } else {
+            //The 4-step sequence raises the frame interrupt as it wraps around
+            if self.frame_counter == FOUR_STEP_IRQ && !self.irq_inhibit {
+                self.irq.assert(IrqSource::FrameCounter);
+            }
+
+            if self.frame_counter >= FOUR_STEP_LENGTH {
+                self.frame_counter = 0;
+            }
+        }
+    }
+
+    //$4015 read, only the frame interrupt flag is here for now.
+    //Reading it acknowledges the frame interrupt
+    pub fn read_status(&mut self) -> u8 {
+        let mut status = 0;
+
+        if self.irq.is_asserted_by(IrqSource::FrameCounter) {
+            status |= 0x40;
+        }
+        if self.irq.is_asserted_by(IrqSource::Dmc) {
+            status |= 0x80;
+        }
+
+        self.irq.release(IrqSource::FrameCounter);
+
+        status
+    }
+
+    //$4017 write
+    pub fn write_frame_counter(&mut self, val: u8) {
+        self.five_step_mode = (val&0x80) > 0;
+        self.irq_inhibit = (val&0x40) > 0;
+        self.frame_counter = 0;
+
+        if self.irq_inhibit {
+            self.irq.release(IrqSource::FrameCounter);
         }
     }
-}
\ No newline at end of file
+}
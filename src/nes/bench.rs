@@ -0,0 +1,105 @@
+use std::fs::File;
+use std::io::Read;
+use std::time::Instant;
+
+use super::cpu::*;
+use super::bus::*;
+
+//A loop that runs a bit of everything: immediate, zero page, indexed and
+//indirect operands, read-modify-write, branches, the stack and subroutines
+const PROGRAM: [u8; 0x23] = [
+    0xA9, 0x01,       //0400 LDA #$01
+    0x65, 0x10,       //0402 ADC $10
+    0x85, 0x10,       //0404 STA $10
+    0xA2, 0x08,       //0406 LDX #$08
+    0x16, 0x20,       //0408 ASL $20,X
+    0x6A,             //040A ROR A
+    0xBD, 0x00, 0x03, //040B LDA $0300,X
+    0x9D, 0x00, 0x03, //040E STA $0300,X
+    0xCA,             //0411 DEX
+    0xD0, 0xF4,       //0412 BNE $0408
+    0xB1, 0x30,       //0414 LDA ($30),Y
+    0xC8,             //0416 INY
+    0x48,             //0417 PHA
+    0x68,             //0418 PLA
+    0x20, 0x20, 0x04, //0419 JSR $0420
+    0x4C, 0x00, 0x04, //041C JMP $0400
+    0xEA,             //041F NOP
+    0xE6, 0x40,       //0420 INC $40
+    0x60,             //0422 RTS
+];
+
+const START: u16 = 0x0400;
+
+//Speed of the NTSC NES's cpu, to compare against
+const NES_CPU_HZ: f64 = 1789773.0;
+
+//Times the cpu on its own, in both execution modes and with both ways of decoding
+//opcodes, running either the built in loop or a 64K image (like the Klaus Dormann
+//functional test) starting at $0400
+pub fn run(image: Option<&str>, instructions: u64) -> bool {
+    let ram = match image {
+        Some(path) => {
+            let mut ram = Vec::new();
+
+            if let Err(why) = File::open(path).and_then(|mut file| file.read_to_end(&mut ram)) {
+                println!("couldn't read {}: {}", path, why);
+                return false;
+            }
+
+            if ram.len() != 0x10000 {
+                println!("{} should be a 64K image, but is {} bytes", path, ram.len());
+                return false;
+            }
+
+            ram
+        }
+
+        None => {
+            let mut ram = vec![0u8; 0x10000];
+            ram[START as usize..START as usize + PROGRAM.len()].copy_from_slice(&PROGRAM);
+
+            ram
+        }
+    };
+
+    let runs = [
+        (ExecutionMode::InstructionStepped, true),
+        (ExecutionMode::InstructionStepped, false),
+        (ExecutionMode::CycleStepped, true),
+        (ExecutionMode::CycleStepped, false),
+    ];
+
+    for &(mode, decode_table) in &runs {
+        let mut bus = FlatBus { ram: ram.clone(), log: None };
+
+        let mut cpu = NESCpu::with_mode(mode);
+        cpu.set_variant(CpuVariant::Nmos6502);
+        cpu.set_decode_table(decode_table);
+        cpu.set_s(0xFF);
+        cpu.set_pc(START);
+
+        let start = Instant::now();
+
+        for _ in 0..instructions {
+            if let Err(err) = cpu.do_instruction(&mut bus) {
+                println!("{:?}, {} decode: stopped, {}", mode, decoder(decode_table), err);
+                return false;
+            }
+        }
+
+        let elapsed = start.elapsed();
+        let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+
+        println!("{:?}, {} decode: {} instructions, {} cycles in {:.3}s, {:.1} million instructions/s, {:.1}x NES speed",
+                 mode, decoder(decode_table), instructions, cpu.cycles(), seconds,
+                 instructions as f64 / seconds / 1e6,
+                 cpu.cycles() as f64 / seconds / NES_CPU_HZ);
+    }
+
+    true
+}
+
+fn decoder(decode_table: bool) -> &'static str {
+    if decode_table { "table" } else { "from_i32" }
+}
@@ -0,0 +1,81 @@
+//Everything the cpu is wired to. On the NES that's the Interconnect, but the
+//cpu doesn't care, so it runs just as well on a flat 64K of RAM for test suites
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, val: u8);
+
+    //Reads without any side effects, for debuggers and logs. Buses that
+    //can't do that for an address (like a hardware register) return None
+    fn peek(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    //Clocks the rest of the system for one cpu cycle (cycle stepped execution)
+    fn tick(&mut self) {}
+
+    //Catches the rest of the system up on a whole instruction (instruction stepped execution)
+    fn update(&mut self, _cpu_cycles: u8) {}
+
+    fn nmi_line(&self) -> bool {
+        false
+    }
+
+    fn irq_line(&self) -> bool {
+        false
+    }
+
+    //(scanline, dot) for traces, if there's a PPU on the bus
+    fn ppu_position(&self) -> (i16, u16) {
+        (0, 0)
+    }
+}
+
+//One cpu bus cycle, as seen from the outside
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BusCycle {
+    pub addr: u16,
+    pub val: u8,
+    pub write: bool,
+}
+
+//64K of RAM and nothing else, for running the cpu on its own.
+//Can also keep a log of every access for checking against test suites
+pub struct FlatBus {
+    pub ram: Vec<u8>,
+
+    pub log: Option<Vec<BusCycle>>,
+}
+
+impl FlatBus {
+    pub fn new() -> FlatBus {
+        FlatBus {
+            ram: vec![0u8; 0x10000],
+
+            log: None,
+        }
+    }
+}
+
+impl Bus for FlatBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let val = self.ram[addr as usize];
+
+        if let Some(ref mut log) = self.log {
+            log.push(BusCycle { addr: addr, val: val, write: false });
+        }
+
+        val
+    }
+
+    fn peek(&self, addr: u16) -> Option<u8> {
+        Some(self.ram[addr as usize])
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        self.ram[addr as usize] = val;
+
+        if let Some(ref mut log) = self.log {
+            log.push(BusCycle { addr: addr, val: val, write: true });
+        }
+    }
+}
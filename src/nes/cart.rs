@@ -1,99 +1,550 @@
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read};
 use std::vec::Vec;
+use std::error::Error;
+use std::fmt;
 
-pub struct NESCart {
-    data: Vec<u8>,
-    ram: [u8; 0x9000]
+use super::irq::IrqLine;
+use super::mapper::{self, Mapper};
+
+//Why a rom couldn't be loaded
+#[derive(Debug)]
+pub enum CartError {
+    //Couldn't open or read the file
+    Io(io::Error),
+
+    //Too short to even hold the 16 byte header
+    TooShort { len: usize },
+
+    //Doesn't start with "NES" followed by MS-DOS end of file
+    BadMagic,
+
+    //The header asks for more PRG/CHR data (and trainer) than the file has
+    Truncated { expected: usize, actual: usize },
+
+    //The header's ROM sizes add up to more than could ever be addressed
+    TooLarge,
+
+    //No implementation of the board the game was made for
+    UnsupportedMapper { mapper: u16, submapper: u8 },
 }
 
-impl NESCart {
-    pub fn new(cartridge: &str) -> NESCart {
-        let mut rom_file = match File::open(cartridge) {
-            Ok(file) => file,
-            Err(why) => panic!("failed to open rom: {}", why),
+impl fmt::Display for CartError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CartError::Io(ref why) => {
+                write!(f, "couldn't read rom: {}", why)
+            }
+
+            CartError::TooShort { len } => {
+                write!(f, "rom is only {} bytes, too short for an iNES header", len)
+            }
+
+            CartError::BadMagic => {
+                write!(f, "not an iNES rom (missing the \"NES\\x1A\" signature)")
+            }
+
+            CartError::Truncated { expected, actual } => {
+                write!(f, "rom is truncated, the header needs {} bytes but the file has {}", expected, actual)
+            }
+
+            CartError::TooLarge => {
+                write!(f, "rom header describes impossibly large PRG/CHR ROM")
+            }
+
+            CartError::UnsupportedMapper { mapper, submapper } => {
+                write!(f, "mapper {} (submapper {}) isn't supported", mapper, submapper)
+            }
+        }
+    }
+}
+
+impl Error for CartError {}
+
+impl From<io::Error> for CartError {
+    fn from(why: io::Error) -> CartError {
+        CartError::Io(why)
+    }
+}
+
+//How the cart wires up the PPU's two internal nametables
+//https://wiki.nesdev.com/w/index.php/Mirroring
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    SingleScreenLower,
+    SingleScreenUpper,
+    FourScreen, //the cart adds another 2KB of VRAM, so all four are separate
+}
+
+impl Mirroring {
+    //Where a nametable address ($2000-$2FFF) lands in the 4 nametables worth of VRAM
+    pub fn nametable_addr(&self, addr: u16) -> usize {
+        let table = match *self {
+            Mirroring::Horizontal => (addr >> 11) & 0x1,
+            Mirroring::Vertical => (addr >> 10) & 0x1,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => (addr >> 10) & 0x3,
         };
 
-        let mut data: Vec<u8> = Vec::new();
+        (table as usize * 0x400) | (addr as usize & 0x3FF)
+    }
+}
+
+//Which CPU/PPU timing the game expects
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TvSystem {
+    Ntsc,
+    Pal,
+    MultiRegion, //runs on either
+    Dendy, //the PAL famiclone timing
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    PlayChoice10,
+    Extended(u8), //NES 2.0 byte 13, famiclones and the like
+}
+
+//Header info obtained from NESDev wiki
+//https://wiki.nesdev.com/w/index.php/INES
+//https://wiki.nesdev.com/w/index.php/NES_2.0
+#[derive(Copy, Clone, Debug)]
+pub struct CartHeader {
+    pub nes2: bool,
+
+    pub prg_rom_size: usize, //in bytes
+    pub chr_rom_size: usize, //in bytes, 0 means the board has CHR RAM instead
 
-        match rom_file.read_to_end(&mut data) {
-            Ok(size) => println!("read rom {} bytes", size),
-            Err(why) => panic!("error reading rom: {}", why),
+    //All in bytes. The NVRAM is kept when the power is off
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+
+    pub mapper: u16, //12 bits with NES 2.0, 8 with iNES
+    pub submapper: u8, //always 0 with iNES
+
+    pub mirroring: Mirroring,
+    pub four_screen: bool, //the cart has its own VRAM, mirroring is ignored
+    pub battery: bool, //the cart has memory that's kept when the power is off
+    pub trainer: bool, //512 bytes between the header and PRG ROM, loaded at $7000
+
+    pub tv_system: TvSystem,
+
+    pub console_type: ConsoleType,
+    pub vs_ppu_type: u8, //only meaningful for the Vs. System
+    pub vs_hardware_type: u8,
+
+    pub misc_roms: u8, //extra ROMs after CHR ROM
+    pub expansion_device: u8, //what should be plugged into the expansion port by default
+}
+
+impl CartHeader {
+    pub const SIZE: usize = 16;
+    pub const TRAINER_SIZE: usize = 512;
+
+    pub fn parse(data: &[u8]) -> Result<CartHeader, CartError> {
+        if data.len() < CartHeader::SIZE {
+            return Err(CartError::TooShort { len: data.len() })
         }
 
-        NESCart {
-            data: data,
-            ram: [0x0; 0x9000]
+        if &data[0..4] != b"NES\x1A" {
+            return Err(CartError::BadMagic)
+        }
+
+        //NES 2.0 is marked by 10 in bits 2 and 3 of byte 7
+        if (data[7] & 0x0C) == 0x08 {
+            CartHeader::parse_nes2(data)
+        } else {
+            Ok(CartHeader::parse_ines(data))
         }
     }
 
-    pub fn none() -> NESCart{
-        NESCart {
-            data: Vec::new(),
-            ram: [0x0; 0x9000]
+    fn parse_ines(data: &[u8]) -> CartHeader {
+        //Old dumps sometimes have a signature like "DiskDude!" in bytes 7-15,
+        //in which case the upper mapper nibble in byte 7 is garbage too
+        let clean = data[12..16].iter().all(|&b| b == 0);
+        let mapper_hi = if clean { data[7] & 0xF0 } else { 0 };
+
+        let battery = (data[6] & 0x2) > 0;
+        let chr_rom_size = data[5] as usize * 0x2000;
+
+        //0 means 8KB, for compatibility with older roms
+        let prg_ram_size = data[8].max(1) as usize * 0x2000;
+
+        let console_type = match data[7] & 0x3 {
+            0x1 if clean => ConsoleType::VsSystem,
+            0x2 if clean => ConsoleType::PlayChoice10,
+            _ => ConsoleType::Nes,
+        };
+
+        CartHeader {
+            nes2: false,
+
+            prg_rom_size: data[4] as usize * 0x4000,
+            chr_rom_size: chr_rom_size,
+
+            prg_ram_size: if battery { 0 } else { prg_ram_size },
+            prg_nvram_size: if battery { prg_ram_size } else { 0 },
+            chr_ram_size: if chr_rom_size == 0 { 0x2000 } else { 0 },
+            chr_nvram_size: 0,
+
+            mapper: (mapper_hi | (data[6] >> 4)) as u16,
+            submapper: 0,
+
+            mirroring: if (data[6] & 0x1) > 0 { Mirroring::Vertical } else { Mirroring::Horizontal },
+            four_screen: (data[6] & 0x8) > 0,
+            battery: battery,
+            trainer: (data[6] & 0x4) > 0,
+
+            tv_system: if clean && (data[9] & 0x1) > 0 { TvSystem::Pal } else { TvSystem::Ntsc },
+
+            console_type: console_type,
+            vs_ppu_type: 0,
+            vs_hardware_type: 0,
+
+            misc_roms: 0,
+            expansion_device: 0,
         }
     }
 
-    pub fn get_pattern_table(&self, num: usize) -> &[u8] {
-        let addr = self.data[4] as usize * 16000 + 0x10 + (0x1000*num);
-        return &self.data.as_slice()[addr..(addr+0x1000)];
+    fn parse_nes2(data: &[u8]) -> Result<CartHeader, CartError> {
+        let console_type = match data[7] & 0x3 {
+            0x0 => ConsoleType::Nes,
+            0x1 => ConsoleType::VsSystem,
+            0x2 => ConsoleType::PlayChoice10,
+            _ => ConsoleType::Extended(data[13] & 0x0F),
+        };
+
+        let (vs_ppu_type, vs_hardware_type) = if console_type == ConsoleType::VsSystem {
+            (data[13] & 0x0F, data[13] >> 4)
+        } else {
+            (0, 0)
+        };
+
+        let prg_rom_size = CartHeader::rom_size(data[4], data[9] & 0x0F, 0x4000).ok_or(CartError::TooLarge)?;
+        let chr_rom_size = CartHeader::rom_size(data[5], data[9] >> 4, 0x2000).ok_or(CartError::TooLarge)?;
+
+        Ok(CartHeader {
+            nes2: true,
+
+            prg_rom_size: prg_rom_size,
+            chr_rom_size: chr_rom_size,
+
+            prg_ram_size: CartHeader::ram_size(data[10] & 0x0F),
+            prg_nvram_size: CartHeader::ram_size(data[10] >> 4),
+            chr_ram_size: CartHeader::ram_size(data[11] & 0x0F),
+            chr_nvram_size: CartHeader::ram_size(data[11] >> 4),
+
+            mapper: ((data[8] as u16 & 0x0F) << 8) | (data[7] & 0xF0) as u16 | (data[6] >> 4) as u16,
+            submapper: data[8] >> 4,
+
+            mirroring: if (data[6] & 0x1) > 0 { Mirroring::Vertical } else { Mirroring::Horizontal },
+            four_screen: (data[6] & 0x8) > 0,
+            battery: (data[6] & 0x2) > 0,
+            trainer: (data[6] & 0x4) > 0,
+
+            tv_system: match data[12] & 0x3 {
+                0x0 => TvSystem::Ntsc,
+                0x1 => TvSystem::Pal,
+                0x2 => TvSystem::MultiRegion,
+                _ => TvSystem::Dendy,
+            },
+
+            console_type: console_type,
+            vs_ppu_type: vs_ppu_type,
+            vs_hardware_type: vs_hardware_type,
+
+            misc_roms: data[14] & 0x3,
+            expansion_device: data[15] & 0x3F,
+        })
     }
 
-    pub fn read(&self, addr: usize) -> u8 {
-        if addr >= self.data.len() {
-            println!("error reading from cart: reached end of rom!");
-            return 0;
+    //ROM sizes are in units (16KB PRG, 8KB CHR) with the extra high nibble, unless
+    //that nibble is $F, when the low byte is EEEEEEMM meaning 2^E * (MM*2 + 1) bytes.
+    //None when that doesn't fit in a usize
+    fn rom_size(lo: u8, hi: u8, unit: usize) -> Option<usize> {
+        if hi == 0x0F {
+            let exponent = (lo >> 2) as u32;
+            let multiplier = (lo & 0x3) as usize * 2 + 1;
+
+            2usize.checked_pow(exponent)?.checked_mul(multiplier)
+        } else {
+            (((hi as usize) << 8) | lo as usize).checked_mul(unit)
         }
-        self.data[addr]
     }
 
-    pub fn read_ram(&self, addr: usize) -> u8 {
-        if addr >= self.ram.len() {
-            println!("error reading from cart: reached end of ram!");
-            return 0;
+    //RAM sizes are shift counts, 64 << n bytes, with 0 meaning none
+    fn ram_size(shift: u8) -> usize {
+        if shift == 0 {
+            0
+        } else {
+            64 << shift as usize
         }
-        self.ram[addr]
     }
 
-    pub fn write_ram(&mut self, addr: usize, val: u8) {
-        if addr >= self.ram.len() {
-            println!("error writing to cart: reached end of ram!");
-            return;
+    //Where PRG ROM starts in the file, after the header and trainer
+    pub fn prg_rom_offset(&self) -> usize {
+        CartHeader::SIZE + if self.trainer { CartHeader::TRAINER_SIZE } else { 0 }
+    }
+
+    //None if the sizes overflow
+    pub fn chr_rom_offset(&self) -> Option<usize> {
+        self.prg_rom_offset().checked_add(self.prg_rom_size)
+    }
+
+    //How big the file has to be to hold everything the header describes
+    pub fn file_size(&self) -> Option<usize> {
+        self.chr_rom_offset()?.checked_add(self.chr_rom_size)
+    }
+}
+
+//A rom file split into its parts, which is what mappers are built from
+pub struct Rom {
+    pub header: CartHeader,
+
+    pub trainer: Option<Vec<u8>>,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+}
+
+impl Rom {
+    pub fn parse(data: &[u8]) -> Result<Rom, CartError> {
+        let header = CartHeader::parse(data)?;
+
+        let chr_rom_offset = header.chr_rom_offset().ok_or(CartError::TooLarge)?;
+        let file_size = header.file_size().ok_or(CartError::TooLarge)?;
+
+        if data.len() < file_size {
+            return Err(CartError::Truncated { expected: file_size, actual: data.len() })
         }
-        self.ram[addr] = val;
+
+        let trainer = if header.trainer {
+            Some(data[CartHeader::SIZE..(CartHeader::SIZE + CartHeader::TRAINER_SIZE)].to_vec())
+        } else {
+            None
+        };
+
+        Ok(Rom {
+            header: header,
+
+            trainer: trainer,
+            prg_rom: data[header.prg_rom_offset()..chr_rom_offset].to_vec(),
+            chr_rom: data[chr_rom_offset..file_size].to_vec(),
+        })
+    }
+}
+
+//The cartridge slot, whatever board is plugged in decides what the
+//cpu sees at $4020-$FFFF and the ppu sees at $0000-$1FFF
+pub struct NESCart {
+    header: Option<CartHeader>,
+    mapper: Box<dyn Mapper>,
+}
+
+impl NESCart {
+    //The cart can pull the cpu's /IRQ line through irq
+    pub fn new(cartridge: &str, irq: IrqLine) -> Result<NESCart, CartError> {
+        let mut data: Vec<u8> = Vec::new();
+
+        File::open(cartridge)?.read_to_end(&mut data)?;
+
+        println!("read rom {} bytes", data.len());
+
+        NESCart::from_bytes(&data, irq)
+    }
+
+    pub fn from_bytes(data: &[u8], irq: IrqLine) -> Result<NESCart, CartError> {
+        let rom = Rom::parse(data)?;
+
+        Ok(NESCart {
+            header: Some(rom.header),
+            mapper: mapper::new(rom, irq)?,
+        })
+    }
+
+    //An empty slot
+    pub fn none() -> NESCart{
+        NESCart {
+            header: None,
+            mapper: Box::new(mapper::NoCart),
+        }
+    }
+
+    //None until a cart is inserted
+    pub fn header(&self) -> Option<&CartHeader> {
+        self.header.as_ref()
+    }
+
+    pub fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.mapper.cpu_read(addr)
+    }
+
+    pub fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        self.mapper.cpu_peek(addr)
+    }
+
+    pub fn cpu_write(&mut self, addr: u16, val: u8) {
+        self.mapper.cpu_write(addr, val);
+    }
+
+    pub fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.mapper.ppu_read(addr)
+    }
+
+    pub fn ppu_write(&mut self, addr: u16, val: u8) {
+        self.mapper.ppu_write(addr, val);
+    }
+
+    pub fn read_nametable(&mut self, addr: u16, ciram: &[u8; 0x1000]) -> u8 {
+        self.mapper.read_nametable(addr, ciram)
+    }
+
+    pub fn write_nametable(&mut self, addr: u16, val: u8, ciram: &mut [u8; 0x1000]) {
+        self.mapper.write_nametable(addr, val, ciram);
+    }
+
+    pub fn ppu_register_write(&mut self, addr: u16, val: u8) {
+        self.mapper.ppu_register_write(addr, val);
+    }
+
+    pub fn cpu_clock(&mut self) {
+        self.mapper.cpu_clock();
+    }
+
+    pub fn ppu_address(&mut self, addr: u16) {
+        self.mapper.ppu_address(addr);
     }
 
-    //Header info obtained from NESDev wiki
-    //https://wiki.nesdev.com/w/index.php/INES 
     pub fn print_header(&self) {
+        let header = match self.header {
+            Some(ref header) => header,
+            None => {
+                println!("No cart inserted");
+                return;
+            }
+        };
+
         println!("ROM Size:");
-        println!("Size of PRG ROM * 16KB: 0x{:0X}", self.data[4] as u16);
-        println!("Size of CHR ROM * 8KB:  0x{:0X}", self.data[5] as u16);
+        println!("PRG ROM: {}KB", header.prg_rom_size / 1024);
+        if header.chr_rom_size > 0 {
+            println!("CHR ROM: {}KB", header.chr_rom_size / 1024);
+        } else {
+            println!("CHR RAM: {}KB", header.chr_ram_size / 1024);
+        }
+        if header.prg_ram_size > 0 {
+            println!("PRG RAM: {}KB", header.prg_ram_size / 1024);
+        }
+        if header.prg_nvram_size > 0 {
+            println!("PRG NVRAM: {}KB", header.prg_nvram_size / 1024);
+        }
+        if header.chr_nvram_size > 0 {
+            println!("CHR NVRAM: {}KB", header.chr_nvram_size / 1024);
+        }
 
         println!("\nFlags:");
         print!("Mirroring: ");
-        if (self.data[6] & 0x1) > 0 {
+        if header.four_screen {
+            println!("Four-screen VRAM (mirroring bit ignored)");
+        } else if header.mirroring == Mirroring::Vertical {
             println!("Vertical (Horizontal Arrangment)");
         } else {
             println!("Horizontal (Vertical Arrangment)");
         }
 
-        if (self.data[6] & 0x2) > 0 {
+        if header.battery {
             println!("Has persistent memory ($6000 - $7FFF)");
         } else {
             println!("No persistent memory");
         }
 
-        if (self.data[6] & 0x4) > 0 {
+        if header.trainer {
             println!("Has trainer ($7000 - $71FF)");
         } else {
             println!("No trainer");
         }
 
-        if (self.data[6] & 0x8) > 0 {
-            println!("Ignore mirror bit (bit 0), provide four-screen vram");
+        println!("TV system: {:?}", header.tv_system);
+
+        if header.console_type != ConsoleType::Nes {
+            println!("Console: {:?}", header.console_type);
+        }
+        if header.console_type == ConsoleType::VsSystem {
+            println!("Vs. PPU type: {}, hardware type: {}", header.vs_ppu_type, header.vs_hardware_type);
+        }
+        if header.misc_roms > 0 {
+            println!("Misc ROMs: {}", header.misc_roms);
+        }
+        if header.expansion_device > 0 {
+            println!("Expansion device: ${:02X}", header.expansion_device);
         }
 
-        println!("\nMapper: {:03}", ((self.data[6] & 0b1111) >> 4) & (self.data[6] & 0b1111));
+        if header.nes2 {
+            println!("\nMapper: {:03}.{} (NES 2.0)", header.mapper, header.submapper);
+        } else {
+            println!("\nMapper: {:03}", header.mapper);
+        }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::mapper::test_rom::TestRom;
+
+    //A header with its ROM size bytes overwritten, and nothing after it
+    fn nes2_header(prg_lo: u8, chr_lo: u8, size_hi: u8) -> Vec<u8> {
+        let mut header = TestRom::new(0).header();
+        header[4] = prg_lo;
+        header[5] = chr_lo;
+        header[9] = size_hi;
+
+        header
+    }
+
+    //2^63 * 7 bytes of PRG ROM
+    #[test]
+    fn huge_exponent_size_is_rejected() {
+        match Rom::parse(&nes2_header(0xFF, 0x00, 0x0F)) {
+            Err(CartError::TooLarge) => {}
+            other => panic!("expected TooLarge, got {:?}", other.err()),
+        }
+    }
+
+    //Each fits in a usize, but not both together
+    #[test]
+    fn sizes_overflowing_the_file_offsets_are_rejected() {
+        let exponent = (0usize.count_zeros() - 1) as u8;
+        let header = nes2_header(exponent << 2, exponent << 2, 0xFF);
+
+        match Rom::parse(&header) {
+            Err(CartError::TooLarge) => {}
+            other => panic!("expected TooLarge, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn missing_rom_data_is_truncated() {
+        match Rom::parse(&nes2_header(0x01, 0x00, 0x00)) {
+            Err(CartError::Truncated { expected, actual }) => assert_eq!((expected, actual), (0x4010, 0x10)),
+            other => panic!("expected Truncated, got {:?}", other.err()),
+        }
+    }
+
+    //PAL and Dendy games still load (they run at NTSC timing), the header keeps what they asked for
+    #[test]
+    fn pal_and_dendy_games_load() {
+        for &(region, tv_system) in &[(0x1, TvSystem::Pal), (0x3, TvSystem::Dendy)] {
+            let mut data = TestRom::new(0).bytes();
+            data[12] = region;
+
+            let cart = NESCart::from_bytes(&data, IrqLine::new()).unwrap_or_else(|why| panic!("{}", why));
+            assert_eq!(cart.header().map(|header| header.tv_system), Some(tv_system));
+        }
+    }
+}
@@ -1,8 +1,12 @@
 use std::ops::{BitOr, BitAnd};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
 
 use super::integer_casting::CastWithNegation;
-use super::interconnect::Interconnect;
+use super::bus::Bus;
 use super::opcode::*;
+use super::trace::*;
 use super::opcode::Op::*;
 use enum_primitive::FromPrimitive;
 
@@ -11,7 +15,9 @@ pub struct CPUStatus {
     carry: bool,
     zero: bool,
     irq_disable: bool,
-    decimal: bool, //not used by the 2A03
+    decimal: bool, //only does anything on the NMOS 6502 variant
+    break_command: bool, //not a real flag, only ever seen in the copy pushed to the stack
+    unused: bool, //always reads back set
     overflow: bool,
     negative: bool,
 }
@@ -23,8 +29,10 @@ impl From<u8> for CPUStatus {
             zero: (val&0x2) > 0,
             irq_disable: (val&0x4) > 0,
             decimal: (val&0x8) > 0,
-            overflow: (val&0x10) > 0,
-            negative: (val&0x20) > 0,
+            break_command: (val&0x10) > 0,
+            unused: (val&0x20) > 0,
+            overflow: (val&0x40) > 0,
+            negative: (val&0x80) > 0,
         }
     }
 }
@@ -45,13 +53,20 @@ impl BitAnd<u8> for CPUStatus {
     }
 }
 
+//The status register's bits
+//https://wiki.nesdev.com/w/index.php/Status_flags
 impl CPUStatus {
+    pub const BREAK: u8 = 0x10;
+    pub const UNUSED: u8 = 0x20;
+
     pub fn new() -> CPUStatus {
         CPUStatus {
             carry: false,
             zero: false,
             irq_disable: false,
             decimal: false,
+            break_command: false,
+            unused: true,
             overflow: false,
             negative: false,
         }
@@ -81,6 +96,8 @@ impl CPUStatus {
         val += (self.zero as u8) << 1;
         val += (self.irq_disable as u8) << 2;
         val += (self.decimal as u8) << 3;
+        val += (self.break_command as u8) << 4;
+        val += (self.unused as u8) << 5;
         val += (self.overflow as u8) << 6;
         val += (self.negative as u8) << 7;
 
@@ -88,6 +105,76 @@ impl CPUStatus {
     }
 }
 
+//Why the cpu stopped instead of running an instruction. The cpu is left
+//on the offending instruction, so a frontend can inspect it or carry on
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CpuError {
+    //An opcode the cpu doesn't know how to run
+    UnknownOpcode { pc: u16, opcode: Opcode },
+
+    //An unofficial opcode while they're turned off
+    UnofficialOpcode { pc: u16, opcode: Opcode },
+
+    //A JAM opcode locked the cpu up, only a reset recovers from this
+    Jammed { pc: u16, op: u8 },
+
+    Breakpoint { pc: u16 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CpuError::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode at 0x{:04X}: {:02X} {:02X} {:02X}",
+                       pc, opcode.op(), opcode.imm1(), opcode.imm2())
+            }
+
+            CpuError::UnofficialOpcode { pc, opcode } => {
+                write!(f, "unofficial opcode {} at 0x{:04X}: {:02X} {:02X} {:02X}",
+                       opcode.info().mnemonic, pc, opcode.op(), opcode.imm1(), opcode.imm2())
+            }
+
+            CpuError::Jammed { pc, op } => {
+                write!(f, "cpu jammed by opcode 0x{:02X} at 0x{:04X}", op, pc)
+            }
+
+            CpuError::Breakpoint { pc } => {
+                write!(f, "breakpoint hit at 0x{:04X}", pc)
+            }
+        }
+    }
+}
+
+impl Error for CpuError {}
+
+//How the cpu keeps the rest of the system in step with itself
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ExecutionMode {
+    //Runs a whole instruction, dummy accesses and all, then clocks everything else for the cycles it took
+    InstructionStepped,
+
+    //Clocks everything else before every bus access, including the dummy
+    //reads and writes, so each access lands on the right cycle (slower)
+    CycleStepped,
+}
+
+//Which 6502 the cpu behaves like
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CpuVariant {
+    //The NES's cpu. Decimal mode is cut out, so the D flag can be set but ADC and SBC ignore it
+    Ricoh2A03,
+
+    //A stock NMOS 6502, with decimal mode ADC and SBC
+    Nmos6502,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Access {
+    Read,
+    Write,
+    ReadModifyWrite,
+}
+
 pub struct NESCpu {
     a: u8, //accumulator
     x: u8, //x-index
@@ -97,10 +184,34 @@ pub struct NESCpu {
     pc: u16, //program counter
 
     p: CPUStatus, //cpu status
+
+    cycles: u64, //cycles elapsed since power on
+
+    mode: ExecutionMode,
+    variant: CpuVariant,
+
+    unofficial_opcodes: bool, //when false they're reported as errors instead of run
+    decode_table: bool, //when false opcodes go through Op::from_i32 instead, to benchmark against
+    jammed: bool, //set by the JAM opcodes, only a reset clears it
+
+    nmi_line: bool, //level of /NMI when it was last sampled
+    nmi_pending: bool,
+    irq_pending: bool,
+
+    page_crossed: bool, //set when the last operand read was indexed into the next page
+
+    breakpoints: HashSet<u16>,
+    last_breakpoint: Option<u16>, //so resuming doesn't stop on the same breakpoint again
+
+    trace: Option<Box<dyn TraceSink>>, //off unless something asks for it
 }
 
 impl NESCpu {
     pub fn new() -> NESCpu {
+        NESCpu::with_mode(ExecutionMode::InstructionStepped)
+    }
+
+    pub fn with_mode(mode: ExecutionMode) -> NESCpu {
         NESCpu {
             a: 0,
             x: 0,
@@ -110,6 +221,26 @@ impl NESCpu {
             pc: 0x8000, //for now just make this the start of PRG ROM in the cart
 
             p: CPUStatus::new(),
+
+            cycles: 0,
+
+            mode: mode,
+            variant: CpuVariant::Ricoh2A03,
+
+            unofficial_opcodes: true,
+            decode_table: true,
+            jammed: false,
+
+            nmi_line: false,
+            nmi_pending: false,
+            irq_pending: false,
+
+            page_crossed: false,
+
+            breakpoints: HashSet::new(),
+            last_breakpoint: None,
+
+            trace: None,
         }
     }
 
@@ -128,6 +259,47 @@ impl NESCpu {
     pub fn p(&self) -> CPUStatus {
         self.p
     }
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+    pub fn mode(&self) -> ExecutionMode {
+        self.mode
+    }
+    pub fn variant(&self) -> CpuVariant {
+        self.variant
+    }
+    pub fn unofficial_opcodes(&self) -> bool {
+        self.unofficial_opcodes
+    }
+    pub fn jammed(&self) -> bool {
+        self.jammed
+    }
+
+    pub fn set_variant(&mut self, variant: CpuVariant) {
+        self.variant = variant;
+    }
+
+    pub fn set_unofficial_opcodes(&mut self, val: bool) {
+        self.unofficial_opcodes = val;
+    }
+
+    pub fn set_decode_table(&mut self, val: bool) {
+        self.decode_table = val;
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    pub fn set_trace_sink(&mut self, sink: Option<Box<dyn TraceSink>>) {
+        self.trace = sink;
+    }
 
     pub fn shift_left(&mut self, val: u8) -> u8 {
         let result = val << 1;
@@ -144,7 +316,7 @@ impl NESCpu {
             self.p.set_negative(false);
         }
 
-        if val == 0 {
+        if result == 0 {
             self.p.set_zero(true);
         } else {
             self.p.set_zero(false);
@@ -153,6 +325,56 @@ impl NESCpu {
         result
     }
 
+    pub fn shift_right(&mut self, val: u8) -> u8 {
+        let result = val >> 1;
+
+        self.p.set_carry((val&0x1) > 0);
+        self.p.set_negative(false);
+        self.p.set_zero(result == 0);
+
+        result
+    }
+
+    //Rotates through the carry flag, so the old carry becomes bit 0
+    pub fn rotate_left(&mut self, val: u8) -> u8 {
+        let result = (val << 1) | (self.p.carry as u8);
+
+        self.p.set_carry((val&0x80) > 0);
+        self.p.set_negative((result&0x80) > 0);
+        self.p.set_zero(result == 0);
+
+        result
+    }
+
+    //Rotates through the carry flag, so the old carry becomes bit 7
+    pub fn rotate_right(&mut self, val: u8) -> u8 {
+        let result = (val >> 1) | ((self.p.carry as u8) << 7);
+
+        self.p.set_carry((val&0x1) > 0);
+        self.p.set_negative((result&0x80) > 0);
+        self.p.set_zero(result == 0);
+
+        result
+    }
+
+    pub fn increment(&mut self, val: u8) -> u8 {
+        let result = val.wrapping_add(1);
+
+        self.p.set_negative((result&0x80) > 0);
+        self.p.set_zero(result == 0);
+
+        result
+    }
+
+    pub fn decrement(&mut self, val: u8) -> u8 {
+        let result = val.wrapping_sub(1);
+
+        self.p.set_negative((result&0x80) > 0);
+        self.p.set_zero(result == 0);
+
+        result
+    }
+
     pub fn set_pc(&mut self, val: u16) {
         self.pc = val;
     }
@@ -203,7 +425,7 @@ impl NESCpu {
         }
     }
     pub fn set_s(&mut self, val: u8) {
-        self.s = val % 0xFF;
+        self.s = val;
     }
 
     pub fn set_p_u8(&mut self, val: u8) {
@@ -213,6 +435,11 @@ impl NESCpu {
         self.p = val;
     }
 
+    //PLP and RTI can't set the break bit or clear the unused bit, they aren't really there
+    fn set_p_pulled(&mut self, val: u8) {
+        self.set_p_u8((val & !CPUStatus::BREAK) | CPUStatus::UNUSED);
+    }
+
     pub fn offset_a(&mut self, val: u8) {
         self.a = self.a.wrapping_add(val);
 
@@ -259,17 +486,21 @@ impl NESCpu {
         }
     }
     pub fn offset_s(&mut self, val: u8) {
-        self.s = self.s.wrapping_add(val) % 0xFF;
+        self.s = self.s.wrapping_add(val);
     }
     pub fn offset_pc(&mut self, val: u16) {
         self.pc = self.pc.wrapping_add(val);
     }
 
     pub fn add_with_carry(&mut self, lhs: u8, rhs: u8) -> u8 {
-        if self.p.decimal {
-            //return self.add_carry_decimal(left, right);
+        if self.p.decimal && self.variant == CpuVariant::Nmos6502 {
+            return self.add_carry_decimal(lhs, rhs);
         }
 
+        self.add_carry_binary(lhs, rhs)
+    }
+
+    fn add_carry_binary(&mut self, lhs: u8, rhs: u8) -> u8 {
         let carry = self.p.carry;
         let mut result = (lhs as u16) + (rhs as u16);
 
@@ -290,7 +521,9 @@ impl NESCpu {
             self.p.set_zero(false);
         }
 
-        if (result as i16) < -128 || (result as i16) > 127 {
+        //Overflow is set when both operands have the same sign
+        //and the result's sign differs from them
+        if ((lhs ^ (result as u8)) & (rhs ^ (result as u8)) & 0x80) > 0 {
             self.p.set_overflow(true);
         } else {
             self.p.set_overflow(false);
@@ -327,12 +560,67 @@ impl NESCpu {
         val
     }
 
+    //SBC is ADC with the operand inverted, the carry flag acts as "not borrow"
     pub fn subtract_with_carry(&mut self, lhs: u8, rhs: u8) -> u8 {
+        if self.p.decimal && self.variant == CpuVariant::Nmos6502 {
+            return self.subtract_carry_decimal(lhs, rhs);
+        }
+
+        self.add_with_carry(lhs, !rhs)
+    }
+
+    //NMOS decimal mode adds each digit separately, correcting any digit over 9.
+    //Z comes from the binary sum, while N and V come from the result before the
+    //high digit is corrected, which is why they look wrong for BCD
+    //http://www.6502.org/tutorials/decimal_mode.html
+    fn add_carry_decimal(&mut self, lhs: u8, rhs: u8) -> u8 {
+        let carry = if self.p.carry { 1 } else { 0 };
+
+        let binary = (lhs as u16) + (rhs as u16) + carry;
+        self.p.set_zero((binary as u8) == 0);
+
+        let mut lo = (lhs as u16 & 0x0F) + (rhs as u16 & 0x0F) + carry;
+        if lo > 0x09 {
+            lo = ((lo + 0x06) & 0x0F) + 0x10;
+        }
+
+        let mut result = (lhs as u16 & 0xF0) + (rhs as u16 & 0xF0) + lo;
+
+        self.p.set_negative((result&0x80) > 0);
+        self.p.set_overflow(((lhs as u16 ^ result) & (rhs as u16 ^ result) & 0x80) > 0);
+
+        if result > 0x9F {
+            result += 0x60;
+        }
+
+        self.p.set_carry(result > 0xFF);
+
+        result as u8
+    }
+
+    //All the flags in decimal SBC come from the binary subtraction,
+    //only the result is corrected
+    fn subtract_carry_decimal(&mut self, lhs: u8, rhs: u8) -> u8 {
+        let borrow = if self.p.carry { 0 } else { 1 };
+
+        self.add_carry_binary(lhs, !rhs);
+
+        let mut lo = (lhs as i16 & 0x0F) - (rhs as i16 & 0x0F) - borrow;
+        if lo < 0 {
+            lo = ((lo - 0x06) & 0x0F) - 0x10;
+        }
+
+        let mut result = (lhs as i16 & 0xF0) - (rhs as i16 & 0xF0) + lo;
+        if result < 0 {
+            result -= 0x60;
+        }
+
+        result as u8
+    }
+
+    pub fn subtract(&mut self, lhs: u8, rhs: u8) -> u8 {
         let mut val = lhs.wrapping_sub(rhs);
         
-        if self.p.carry {
-            val += 1;
-        }
         if lhs >= rhs {
             self.p.set_carry(true);
         } else {
@@ -352,14 +640,9 @@ impl NESCpu {
         val
     }
 
-    pub fn subtract(&mut self, lhs: u8, rhs: u8) -> u8 {
-        let mut val = lhs.wrapping_sub(rhs);
+    pub fn and(&mut self, lhs: u8, rhs: u8) -> u8 {
+        let mut val = lhs & rhs;
         
-        if lhs >= rhs {
-            self.p.set_carry(true);
-        } else {
-            self.p.set_carry(false);
-        }
         if val == 0 {
             self.p.set_zero(true);
         } else {
@@ -374,8 +657,8 @@ impl NESCpu {
         val
     }
 
-    pub fn and(&mut self, lhs: u8, rhs: u8) -> u8 {
-        let mut val = lhs & rhs;
+    pub fn or(&mut self, lhs: u8, rhs: u8) -> u8 {
+        let mut val = lhs | rhs;
         
         if val == 0 {
             self.p.set_zero(true);
@@ -391,9 +674,9 @@ impl NESCpu {
         val
     }
 
-    pub fn or(&mut self, lhs: u8, rhs: u8) -> u8 {
-        let mut val = lhs | rhs;
-        
+    pub fn xor(&mut self, lhs: u8, rhs: u8) -> u8 {
+        let val = lhs ^ rhs;
+
         if val == 0 {
             self.p.set_zero(true);
         } else {
@@ -408,467 +691,1188 @@ impl NESCpu {
         val
     }
 
-    pub fn push_stack(&mut self, interconnect: &mut Interconnect, val: u8) {
-        interconnect.write_mem(0x100 + self.s as usize, val);
+    //Zero flag comes from A & M, while V and N are copied straight from bits 6 and 7 of M
+    pub fn bit_test(&mut self, lhs: u8, rhs: u8) {
+        self.p.set_zero((lhs & rhs) == 0);
+        self.p.set_overflow((rhs&0x40) > 0);
+        self.p.set_negative((rhs&0x80) > 0);
+    }
+
+    //Taken branches cost one extra cycle, and another if they land on a different page
+    fn branch<B: Bus>(&mut self, bus: &mut B, opcode: &Opcode) -> u8 {
+        let from = self.pc;
+        let (target, crossed) = self.resolve_address(bus, opcode, Access::Read);
+
+        self.pc = target;
+
+        self.dummy_read(bus, from);
+
+        if crossed {
+            let uncorrected = (from&0xFF00) | (target&0x00FF);
+            self.dummy_read(bus, uncorrected);
+
+            2
+        } else {
+            1
+        }
+    }
+
+    pub fn push_stack<B: Bus>(&mut self, bus: &mut B, val: u8) {
+        let s = self.s;
+        self.write(bus, 0x100 + s as u16, val);
         self.offset_s(0xFF);
     }
 
-    pub fn pop_stack(&mut self, interconnect: &mut Interconnect) -> u8 {
+    pub fn pop_stack<B: Bus>(&mut self, bus: &mut B) -> u8 {
         self.offset_s(1);
-        let val = interconnect.read_mem(0x100 + self.s as usize);
+        let s = self.s;
+        let val = self.read(bus, 0x100 + s as u16);
 
         val
     }
 
-    pub fn do_nmi(&mut self, interconnect: &mut Interconnect) {
-        let return_point = self.pc;
+    //Every bus access the cpu makes goes through these. When cycle stepped,
+    //each access is one cpu cycle and the rest of the system is clocked before it lands
+    fn read<B: Bus>(&mut self, bus: &mut B, addr: u16) -> u8 {
+        if self.mode == ExecutionMode::CycleStepped {
+            bus.tick();
+            self.cycles += 1;
+            self.sample_nmi(bus);
+        }
 
-        let p = self.p.to_u8();
-        self.push_stack(interconnect, p);
-        self.push_stack(interconnect, ((return_point&0xFF00) >> 8) as u8);
-        self.push_stack(interconnect, (return_point&0xFF) as u8);
+        bus.read(addr)
+    }
 
-        let addr_lo = interconnect.read_absolute(0xFFFC) as u16;
-        let addr_hi = interconnect.read_absolute(0xFFFD) as u16;
+    fn write<B: Bus>(&mut self, bus: &mut B, addr: u16, val: u8) {
+        if self.mode == ExecutionMode::CycleStepped {
+            bus.tick();
+            self.cycles += 1;
+            self.sample_nmi(bus);
+        }
 
-        self.pc = (addr_hi << 8) | addr_lo;
+        bus.write(addr, val);
     }
 
-    //6502 opcode info http://obelisk.me.uk/6502/reference.html
-    pub fn do_instruction(&mut self, interconnect: &mut Interconnect) -> bool {
-        //Read 3 bytes (1st is opcode, 2nd is first operand (if any), 3rd is second operand (if any))
-        let op = interconnect.read_mem(self.pc as usize) as u32;
-        let imm1 = (interconnect.read_mem((self.pc.wrapping_add(1)) as usize) as u32) << 8;
-        let imm2 = (interconnect.read_mem((self.pc.wrapping_add(2)) as usize) as u32) << 16;
+    //Reads and writes the real cpu makes but throws away. They still reach the
+    //bus in both modes, since they can have side effects (like clearing vblank,
+    //or the MMC1 seeing a read-modify-write's first write)
+    fn dummy_read<B: Bus>(&mut self, bus: &mut B, addr: u16) {
+        let _ = self.read(bus, addr);
+    }
 
-        let opcode = Opcode::new(op | imm1 | imm2);
+    fn dummy_write<B: Bus>(&mut self, bus: &mut B, addr: u16, val: u8) {
+        self.write(bus, addr, val);
+    }
 
-         println!("pc: 0x{:04X}", self.pc);
+    //Works out the address an instruction operates on, and whether indexing it
+    //carried into the next page, making the same dummy reads the 6502 does along the way
+    //http://nesdev.com/6502_cpu.txt
+    fn resolve_address<B: Bus>(&mut self, bus: &mut B, opcode: &Opcode, access: Access) -> (u16, bool) {
+        match opcode.info().mode {
+            //The operand byte itself, which has already been read
+            AddressingMode::Immediate => {
+                (self.pc.wrapping_sub(1), false)
+            }
 
-        //using a nifty crate that can convert integers to enums
-        //to make pattern matching nicer
-        match Op::from_i32(opcode.op() as i32) {
-            Some(op) => {
-                print!("{:?} ", op);
-                
-                match op {
+            AddressingMode::ZeroPage => {
+                (opcode.imm1() as u16, false)
+            }
 
-                    //IMPLIED
-                    Op::BRKImmediate => {
-                        let return_point = self.pc.wrapping_add(1);
+            //Zero page indexing never leaves the zero page
+            AddressingMode::ZeroPageX => {
+                self.dummy_read(bus, opcode.imm1() as u16);
+                (opcode.imm1().wrapping_add(self.x) as u16, false)
+            }
 
-                        let p = self.p.to_u8();
-                        self.push_stack(interconnect, ((return_point&0xFF00) >> 8) as u8);
-                        self.push_stack(interconnect, (return_point&0xFF) as u8);
-                        self.push_stack(interconnect, p);
+            AddressingMode::ZeroPageY => {
+                self.dummy_read(bus, opcode.imm1() as u16);
+                (opcode.imm1().wrapping_add(self.y) as u16, false)
+            }
 
-                        let addr_lo = interconnect.read_absolute(0xFFFE) as u16;
-                        let addr_hi = interconnect.read_absolute(0xFFFF) as u16;
+            AddressingMode::Absolute => {
+                (opcode.abs_addr() as u16, false)
+            }
 
-                        self.pc = (addr_hi << 8) | addr_lo;
-                    }
+            AddressingMode::AbsoluteX => {
+                let x = self.x;
+                self.indexed_address(bus, opcode.abs_addr() as u16, x, access)
+            }
 
-                    Op::ORAIndirectX => {
-                        let mut val = interconnect.read_indexed_indirect_x(opcode.imm1() as usize, self.x as usize);
-                        val = self.a | val;
+            AddressingMode::AbsoluteY => {
+                let y = self.y;
+                self.indexed_address(bus, opcode.abs_addr() as u16, y, access)
+            }
 
-                        self.set_a(val);
+            //Only JMP uses this. The pointer's high byte is read without carrying
+            //into the next page, so JMP ($10FF) reads $10FF and $1000
+            AddressingMode::Indirect => {
+                let pointer = opcode.abs_addr() as u16;
+                let lo = self.read(bus, pointer) as u16;
+                let hi = self.read(bus, (pointer&0xFF00) | (pointer.wrapping_add(1)&0x00FF)) as u16;
 
-                        self.offset_pc(2);
-                    }
+                ((hi << 8) | lo, false)
+            }
 
-                    //ORs the accumulator with memory
-                    //(modifies zero and negative flag)
-                    Op::ORAZeroPage => {
-                        let val = self.a | interconnect.read_zero_page(opcode.imm1() as usize);
+            //The pointer is indexed before it's read, and stays in the zero page
+            AddressingMode::IndirectX => {
+                self.dummy_read(bus, opcode.imm1() as u16);
 
-                        self.set_a(val);
+                let pointer = opcode.imm1().wrapping_add(self.x);
+                let lo = self.read(bus, pointer as u16) as u16;
+                let hi = self.read(bus, pointer.wrapping_add(1) as u16) as u16;
 
-                        self.offset_pc(2);
-                    }
+                ((hi << 8) | lo, false)
+            }
 
-                    Op::ASLZeroPage => {
-                        let mut val = interconnect.read_zero_page(opcode.imm1() as usize);
-                        val = self.shift_left(val);
+            //The pointer is read first (wrapping in the zero page), then the address it holds is indexed
+            AddressingMode::IndirectY => {
+                let pointer = opcode.imm1();
+                let lo = self.read(bus, pointer as u16) as u16;
+                let hi = self.read(bus, pointer.wrapping_add(1) as u16) as u16;
 
-                        interconnect.write_zero_page(opcode.imm1() as usize, val);
+                let y = self.y;
+                self.indexed_address(bus, (hi << 8) | lo, y, access)
+            }
 
-                        self.offset_pc(2);
-                    }
+            //Relative to the next instruction, pc has already stepped over this one
+            AddressingMode::Relative => {
+                let target = self.pc.wrapping_add(opcode.imm1().cast_with_neg());
 
-                    //Branch if Plus (adds to the program counter if negative flag is clear)
-                    Op::BPLRelative => {
-                        if self.p.negative == false {
-                            print!("Branching from 0x{:04X}", self.pc);
-                            self.offset_pc(opcode.imm1().cast_with_neg());
-                            println!(" to 0x{:04X} (PC + 0x{:02X})", self.pc + 2, opcode.imm1().cast_with_neg());
-                        }
+                (target, (self.pc&0xFF00) != (target&0xFF00))
+            }
 
-                        self.offset_pc(2);
-                    }
+            //Implied and accumulator instructions never ask for one
+            mode => unreachable!("{:?} addressing has no operand address", mode),
+        }
+    }
 
-                    //Clears the Carry flag
-                    Op::CLCImplied => {
-                        self.p.set_carry(false);
+    //The index is added to the low byte first and the cpu reads from there,
+    //fixing up the high byte a cycle later if it carried. Reads that didn't carry
+    //can use that first read, everything else throws it away
+    fn indexed_address<B: Bus>(&mut self, bus: &mut B, base: u16, index: u8, access: Access) -> (u16, bool) {
+        let addr = base.wrapping_add(index as u16);
+        let uncorrected = (base&0xFF00) | (addr&0x00FF);
+        let crossed = uncorrected != addr;
 
-                        self.offset_pc(1);
-                    }
+        if crossed || access != Access::Read {
+            self.dummy_read(bus, uncorrected);
+        }
 
-                    Op::ORAAbsoluteY => {
-                        let mut val = interconnect.read_absolute_indexed_y(opcode.abs_addr(), self.y as usize);
-                        let a = self.a;
+        (addr, crossed)
+    }
 
-                        val = self.or(a, val);
+    fn read_operand<B: Bus>(&mut self, bus: &mut B, opcode: &Opcode) -> u8 {
+        if opcode.info().mode == AddressingMode::Immediate {
+            return opcode.imm1();
+        }
 
-                        self.set_a(val);
+        let (addr, crossed) = self.resolve_address(bus, opcode, Access::Read);
+        self.page_crossed = crossed;
 
-                        self.offset_pc(3);
-                    }
+        self.read(bus, addr)
+    }
 
-                    //Pushes return point onto stack (pc + 3), then sets pc to absolute address
-                    Op::JSRAbsolute => {
-                        let return_point = self.pc.wrapping_add(3);
-                        let addr = opcode.abs_addr() as u16;
+    fn write_operand<B: Bus>(&mut self, bus: &mut B, opcode: &Opcode, val: u8) {
+        let (addr, _) = self.resolve_address(bus, opcode, Access::Write);
+        self.write(bus, addr, val);
+    }
 
-                        self.push_stack(interconnect, ((return_point&0xFF00) >> 8) as u8);
-                        self.push_stack(interconnect, (return_point&0xFF) as u8);
-                        self.pc = addr;
-                    }
-                    
-                    Op::BITZeroPage => {
-                        let val = interconnect.read_zero_page(opcode.imm1() as usize);
-                        let a = self.a;
+    //Read-modify-write instructions write the unmodified value back
+    //before writing the result
+    fn modify_operand<B: Bus, F>(&mut self, bus: &mut B, opcode: &Opcode, op: F) -> u8
+        where F: FnOnce(&mut NESCpu, u8) -> u8 {
+        let (addr, _) = self.resolve_address(bus, opcode, Access::ReadModifyWrite);
+        let val = self.read(bus, addr);
 
-                        let _ = self.and(a, val);
-                        
-                        self.p.set_overflow((val&0x40) > 0);
-                        self.p.set_negative((val&0x80) > 0);
+        self.dummy_write(bus, addr, val);
+        let val = op(self, val);
+        self.write(bus, addr, val);
 
-                        self.offset_pc(2);
-                    }
+        val
+    }
 
-                    Op::ANDZeroPage => {
-                        let mut val = interconnect.read_zero_page(opcode.imm1() as usize);
-                        let a = self.a;
+    //Cycle stepped execution has already clocked the system on every access,
+    //otherwise the rest of the system catches up on the whole instruction at once
+    fn finish_step<B: Bus>(&mut self, bus: &mut B, start_cycles: u64, cycles: u8) -> u8 {
+        match self.mode {
+            ExecutionMode::CycleStepped => {
+                (self.cycles - start_cycles) as u8
+            }
 
-                        let val = self.and(a, val);
+            ExecutionMode::InstructionStepped => {
+                self.cycles += cycles as u64;
+                bus.update(cycles);
 
-                        self.set_a(val);
+                cycles
+            }
+        }
+    }
 
-                        self.offset_pc(2);
-                    }
+    //Interrupt vectors
+    //https://wiki.nesdev.com/w/index.php/CPU_interrupts
+    pub const NMI_VECTOR: u16 = 0xFFFA;
+    pub const RESET_VECTOR: u16 = 0xFFFC;
+    pub const IRQ_VECTOR: u16 = 0xFFFE;
+
+    //Reset goes through the interrupt sequence with the writes turned into reads,
+    //so the stack pointer still drops by 3 but nothing is pushed
+    pub fn reset<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let start_cycles = self.cycles;
+
+        let pc = self.pc;
+        self.dummy_read(bus, pc);
+        self.dummy_read(bus, pc);
+
+        for _ in 0..3 {
+            let s = self.s;
+            self.dummy_read(bus, 0x100 + s as u16);
+            self.offset_s(0xFF);
+        }
 
-                    //AND Accumulator with immediate
-                    Op::ANDImmediate => {
-                        let a = self.a;
-                        let val = self.and(a, opcode.imm1());
+        self.p.set_irq_disable(true);
+        self.jammed = false;
+        self.nmi_pending = false;
+        self.irq_pending = false;
 
-                        self.set_a(val);
+        let addr_lo = self.read(bus, NESCpu::RESET_VECTOR) as u16;
+        let addr_hi = self.read(bus, NESCpu::RESET_VECTOR.wrapping_add(1)) as u16;
 
-                        self.offset_pc(2);
-                    }
+        self.pc = (addr_hi << 8) | addr_lo;
 
-                    Op::JMPAbsolute => {
-                        self.pc = opcode.abs_addr() as u16;
-                    }
+        self.finish_step(bus, start_cycles, 7)
+    }
 
-                    Op::RTSImplied => {
-                        let hi = self.pop_stack(interconnect) as u16;
-                        let lo = self.pop_stack(interconnect) as u16;
-                        let ret = ((hi << 8) | lo).wrapping_sub(1);
+    //Shared by BRK, NMI and IRQ. Pushes pc then the status register, which only
+    //has the break bit set when it came from BRK
+    fn interrupt<B: Bus>(&mut self, bus: &mut B, vector: u16, brk: bool) {
+        //BRK has already read its padding byte, hardware interrupts
+        //read the opcode they're replacing twice instead
+        if !brk {
+            let pc = self.pc;
+            self.dummy_read(bus, pc);
+            self.dummy_read(bus, pc);
+        }
 
-                        self.pc = ret;
+        //The only difference between the two is the break bit in the pushed status
+        let return_point = self.pc;
+        let p = if brk {
+            self.p.to_u8() | CPUStatus::BREAK | CPUStatus::UNUSED
+        } else {
+            (self.p.to_u8() & !CPUStatus::BREAK) | CPUStatus::UNUSED
+        };
 
-                        println!("S: {:04X}", self.s);
-                    }
+        self.push_stack(bus, ((return_point&0xFF00) >> 8) as u8);
+        self.push_stack(bus, (return_point&0xFF) as u8);
+        self.push_stack(bus, p);
 
-                    Op::ADCAbsolute => {
-                        let mut val = interconnect.read_absolute(opcode.abs_addr());
-                        let a = self.a;
-                        val = self.add_with_carry(a, val);
+        self.p.set_irq_disable(true);
 
-                        self.set_a(val);
+        let addr_lo = self.read(bus, vector) as u16;
+        let addr_hi = self.read(bus, vector.wrapping_add(1)) as u16;
 
-                        self.offset_pc(3);
-                    }
+        self.pc = (addr_hi << 8) | addr_lo;
+    }
 
-                    //Set Interrupt Disable (Sets the I flag to true)
-                    Op::SEIImplied => {
-                        self.p.set_irq_disable(true);
+    //NMI is edge triggered, so the cpu remembers the line going low even if it
+    //goes high again before the instruction finishes
+    fn sample_nmi<B: Bus>(&mut self, bus: &mut B) {
+        let line = bus.nmi_line();
 
-                        self.offset_pc(1);
-                    }
+        if line && !self.nmi_line {
+            self.nmi_pending = true;
+        }
 
-                    Op::STYZeroPage => {
-                        interconnect.write_zero_page(opcode.imm1() as usize, self.y);
+        self.nmi_line = line;
+    }
 
-                        self.offset_pc(2);
-                    }
+    //Interrupts are polled at the end of each instruction. IRQ is level triggered,
+    //so it's only taken if the line is still asserted and I is clear. CLI, SEI and PLP
+    //change I after the poll, so they see the old value
+    fn poll_interrupts<B: Bus>(&mut self, bus: &mut B, irq_disable: bool) {
+        self.sample_nmi(bus);
 
-                    Op::STAZeroPage => {
-                        interconnect.write_zero_page(opcode.imm1() as usize, self.a);
+        self.irq_pending = bus.irq_line() && !irq_disable;
+    }
 
-                        self.offset_pc(2);
-                    }
+    //Unofficial opcodes are dispatched on their mnemonic from the opcode table
+    //https://wiki.nesdev.com/w/index.php/Programming_with_unofficial_opcodes
+    fn do_unofficial<B: Bus>(&mut self, bus: &mut B, opcode: &Opcode) -> Result<(), CpuError> {
+        match opcode.info().mnemonic {
+            //The multi-byte NOPs still read their operand
+            "NOP" => {
+                if opcode.info().mode != AddressingMode::Implied {
+                    let _ = self.read_operand(bus, opcode);
+                }
+            }
 
-                    Op::STXZeroPage => {
-                        interconnect.write_zero_page(opcode.imm1() as usize, self.x);
+            "SBC" => {
+                let val = self.read_operand(bus, opcode);
+                let a = self.a;
+                let val = self.subtract_with_carry(a, val);
 
-                        self.offset_pc(2);
-                    }
+                self.set_a(val);
+            }
 
-                    Op::DEYImplied => {
-                        self.offset_y(0xFF);
+            //Loads both the accumulator and x-index
+            "LAX" => {
+                let val = self.read_operand(bus, opcode);
 
-                        self.offset_pc(1);
-                    }
+                self.set_a(val);
+                self.set_x(val);
+            }
 
-                    //Store Accumulator (Stores a into memory with absolute addressing)
-                    Op::STAAbsolute => {
-                        interconnect.write_absolute(opcode.abs_addr(), self.a);
+            //Stores A & X without touching the flags
+            "SAX" => {
+                let val = self.a & self.x;
+                self.write_operand(bus, opcode, val);
+            }
 
-                        self.offset_pc(3);
-                    }
+            //DEC then CMP
+            "DCP" => {
+                self.modify_operand(bus, opcode, |cpu, val| {
+                    let val = val.wrapping_sub(1);
+                    let a = cpu.a;
+                    let _ = cpu.subtract(a, val);
 
-                    Op::STAIndirectY => {
-                        interconnect.write_indexed_indirect_y(opcode.imm1() as usize, self.y as usize, self.a);
+                    val
+                });
+            }
 
-                        self.offset_pc(2);
-                    }
+            //INC then SBC
+            "ISC" => {
+                self.modify_operand(bus, opcode, |cpu, val| {
+                    let val = val.wrapping_add(1);
+                    let a = cpu.a;
+                    let a = cpu.subtract_with_carry(a, val);
+                    cpu.set_a(a);
 
-                    //Store Accumulator (Stores into memory with zero paged x addressing)
-                    Op::STAZeroPageX => {
-                        interconnect.write_zero_paged_indexed_x(opcode.imm1() as usize, self.x as usize, self.a);
+                    val
+                });
+            }
 
-                        self.offset_pc(2);
-                    }
+            //ASL then ORA
+            "SLO" => {
+                self.modify_operand(bus, opcode, |cpu, val| {
+                    let val = cpu.shift_left(val);
+                    let a = cpu.a;
+                    let a = cpu.or(a, val);
+                    cpu.set_a(a);
 
-                    Op::TYAImplied => {
-                        let y = self.y;
-                        self.set_a(y);
+                    val
+                });
+            }
+
+            //ROL then AND
+            "RLA" => {
+                self.modify_operand(bus, opcode, |cpu, val| {
+                    let val = cpu.rotate_left(val);
+                    let a = cpu.a;
+                    let a = cpu.and(a, val);
+                    cpu.set_a(a);
+
+                    val
+                });
+            }
+
+            //LSR then EOR
+            "SRE" => {
+                self.modify_operand(bus, opcode, |cpu, val| {
+                    let val = cpu.shift_right(val);
+                    let a = cpu.a;
+                    let a = cpu.xor(a, val);
+                    cpu.set_a(a);
+
+                    val
+                });
+            }
+
+            //ROR then ADC
+            "RRA" => {
+                self.modify_operand(bus, opcode, |cpu, val| {
+                    let val = cpu.rotate_right(val);
+                    let a = cpu.a;
+                    let a = cpu.add_with_carry(a, val);
+                    cpu.set_a(a);
+
+                    val
+                });
+            }
+
+            //AND, then copies the negative flag into carry
+            "ANC" => {
+                let a = self.a;
+                let val = self.and(a, opcode.imm1());
+
+                self.set_a(val);
+                self.p.set_carry((val&0x80) > 0);
+            }
+
+            //AND then LSR A
+            "ALR" => {
+                let a = self.a;
+                let val = self.and(a, opcode.imm1());
+
+                self.a = self.shift_right(val);
+            }
+
+            //AND then ROR A, except C is bit 6 and V is bit 6 xor bit 5
+            "ARR" => {
+                let a = self.a;
+                let val = self.and(a, opcode.imm1());
+                let val = self.rotate_right(val);
+
+                self.a = val;
+                self.p.set_carry((val&0x40) > 0);
+                self.p.set_overflow((((val >> 6) ^ (val >> 5)) & 0x1) > 0);
+            }
+
+            //X = (A & X) - operand, setting flags like CMP
+            "AXS" => {
+                let ax = self.a & self.x;
+                let val = self.subtract(ax, opcode.imm1());
+
+                self.x = val;
+            }
+
+            //These two mix in an analog "magic" constant, 0xEE is what most 2A03s show
+            "XAA" => {
+                let val = (self.a | 0xEE) & self.x & opcode.imm1();
+
+                self.set_a(val);
+            }
+
+            "LXA" => {
+                let val = (self.a | 0xEE) & opcode.imm1();
+
+                self.set_a(val);
+                self.set_x(val);
+            }
+
+            "LAS" => {
+                let val = self.read_operand(bus, opcode) & self.s;
+
+                self.s = val;
+                self.set_a(val);
+                self.set_x(val);
+            }
+
+            "TAS" => {
+                self.s = self.a & self.x;
+
+                let (s, y) = (self.s, self.y);
+                self.store_high_and(bus, opcode, s, y);
+            }
+
+            "AHX" => {
+                let (ax, y) = (self.a & self.x, self.y);
+                self.store_high_and(bus, opcode, ax, y);
+            }
+
+            "SHX" => {
+                let (x, y) = (self.x, self.y);
+                self.store_high_and(bus, opcode, x, y);
+            }
 
+            "SHY" => {
+                let (y, x) = (self.y, self.x);
+                self.store_high_and(bus, opcode, y, x);
+            }
+
+            //Locks the cpu up until it's reset, pc stays on the JAM
+            "JAM" => {
+                self.jammed = true;
+                self.offset_pc(0xFFFF);
+
+                return Err(CpuError::Jammed { pc: self.pc, op: opcode.op() })
+            }
+
+            _ => {
+                return Err(CpuError::UnknownOpcode { pc: self.pc.wrapping_sub(opcode.info().len as u16), opcode: *opcode })
+            }
+        }
+
+        Ok(())
+    }
+
+    //The SH* stores AND the value with the high byte of the base address plus one,
+    //and when the index crosses a page that value replaces the high byte of the address
+    fn store_high_and<B: Bus>(&mut self, bus: &mut B, opcode: &Opcode, val: u8, index: u8) {
+        let (addr, crossed) = self.resolve_address(bus, opcode, Access::Write);
+        let base = addr.wrapping_sub(index as u16);
+
+        let val = val & ((base >> 8) as u8).wrapping_add(1);
+
+        let addr = if crossed {
+            ((val as u16) << 8) | (addr&0x00FF)
+        } else {
+            addr
+        };
+
+        self.write(bus, addr, val);
+    }
+
+    //Runs the next instruction, or services a pending interrupt in its place
+    //6502 opcode info http://obelisk.me.uk/6502/reference.html
+    pub fn do_instruction<B: Bus>(&mut self, bus: &mut B) -> Result<u8, CpuError> {
+        if self.jammed {
+            return Err(CpuError::Jammed { pc: self.pc, op: bus.peek(self.pc).unwrap_or(0) })
+        }
+
+        //Stops before running the instruction, calling again runs it
+        if !self.breakpoints.is_empty() && self.breakpoints.contains(&self.pc) &&
+            self.last_breakpoint != Some(self.pc) {
+            self.last_breakpoint = Some(self.pc);
+            return Err(CpuError::Breakpoint { pc: self.pc })
+        }
+        self.last_breakpoint = None;
+
+        let start_cycles = self.cycles;
+
+        if self.nmi_pending || self.irq_pending {
+            let vector = if self.nmi_pending {
+                NESCpu::NMI_VECTOR
+            } else {
+                NESCpu::IRQ_VECTOR
+            };
+
+            self.nmi_pending = false;
+            self.interrupt(bus, vector, false);
+
+            let cycles = self.finish_step(bus, start_cycles, 7);
+            let irq_disable = self.p.irq_disable;
+            self.poll_interrupts(bus, irq_disable);
+
+            return Ok(cycles)
+        }
+
+        let irq_disable = self.p.irq_disable;
+        let position = bus.ppu_position();
+
+        //Read the opcode, then however many operand bytes it takes (up to 2)
+        let pc = self.pc;
+        let op = self.read(bus, pc) as u32;
+        let len = OP_TABLE[op as usize].len;
+
+        let imm1 = if len > 1 {
+            (self.read(bus, pc.wrapping_add(1)) as u32) << 8
+        } else {
+            0
+        };
+        let imm2 = if len > 2 {
+            (self.read(bus, pc.wrapping_add(2)) as u32) << 16
+        } else {
+            0
+        };
+
+        let opcode = Opcode::new(op | imm1 | imm2);
+
+        if let Some(ref mut trace) = self.trace {
+            trace.trace(&TraceRecord {
+                pc: pc,
+                opcode: opcode,
+
+                a: self.a,
+                x: self.x,
+                y: self.y,
+                s: self.s,
+                p: self.p.to_u8(),
+
+                cycle: start_cycles,
+
+                scanline: position.0,
+                dot: position.1,
+            });
+        }
+
+        //Step over the instruction up front, jumps and branches
+        //then work relative to (or overwrite) the next pc
+        self.offset_pc(len as u16);
+
+        //One byte instructions still read the byte after the opcode
+        if len == 1 {
+            let pc = self.pc;
+            self.dummy_read(bus, pc);
+        }
+
+        let mut extra_cycles = 0;
+        self.page_crossed = false;
+
+        //Decoded with a table lookup, the match itself compiles down to a jump table.
+        //from_i32 compares against every variant in turn, it's kept to benchmark against
+        let op = if self.decode_table {
+            opcode.decode()
+        } else {
+            Op::from_i32(opcode.op() as i32)
+        };
+
+        match op {
+            Some(op) => {
+                match op {
+
+                    Op::BRKImmediate => {
+                        //BRK skips the padding byte after the opcode when it returns
                         self.offset_pc(1);
+
+                        self.interrupt(bus, NESCpu::IRQ_VECTOR, true);
                     }
 
-                    //Transfers x-index into stack pointer
-                    Op::TXSImplied => {
-                        self.s = self.x;
+                    //ORs the accumulator with memory
+                    //(modifies zero and negative flag)
+                    Op::ORAIndirectX | Op::ORAZeroPage | Op::ORAImmediate | Op::ORAAbsolute |
+                    Op::ORAIndirectY | Op::ORAZeroPageX | Op::ORAAbsoluteY | Op::ORAAbsoluteX => {
+                        let val = self.read_operand(bus, &opcode);
+                        let a = self.a;
+                        let val = self.or(a, val);
 
-                        self.offset_pc(1);
+                        self.set_a(val);
                     }
 
-                    //Loads operand into y-index (modifies zero and negatives flags)
-                    Op::LDYImmediate => {
-                        self.set_y(opcode.imm1());
+                    //Shifts left, bit 7 goes into the carry flag
+                    Op::ASLZeroPage | Op::ASLAbsolute | Op::ASLZeroPageX | Op::ASLAbsoluteX => {
+                        self.modify_operand(bus, &opcode, NESCpu::shift_left);
+                    }
 
-                        self.offset_pc(2);
+                    //Pushes the status register, with the break and unused bits set
+                    Op::PHPImplied => {
+                        let p = self.p.to_u8() | CPUStatus::BREAK | CPUStatus::UNUSED;
+                        self.push_stack(bus, p);
                     }
 
-                    //Loads operand into x-index (modifies zero and negatives flags)
-                    Op::LDXImmediate => {
-                        self.set_x(opcode.imm1());
+                    Op::ASLAccumulator => {
+                        let a = self.a;
+                        self.a = self.shift_left(a);
+                    }
 
-                        self.offset_pc(2);
+                    //Branch if Plus (adds to the program counter if negative flag is clear)
+                    Op::BPLRelative => {
+                        if self.p.negative == false {
+                            extra_cycles = self.branch(bus, &opcode);
+                        }
                     }
 
-                    Op::LDXZeroPage => {
-                        let val = interconnect.read_zero_page(opcode.imm1() as usize);
+                    //Clears the Carry flag
+                    Op::CLCImplied => {
+                        self.p.set_carry(false);
+                    }
 
-                        self.set_x(val);
+                    //Pushes return point onto stack (last byte of the JSR), then sets pc to absolute address
+                    Op::JSRAbsolute => {
+                        let return_point = self.pc.wrapping_sub(1);
+                        let addr = opcode.abs_addr() as u16;
+
+                        //Internal cycle while the cpu holds the stack pointer on the bus
+                        let s = self.s;
+                        self.dummy_read(bus, 0x100 + s as u16);
+
+                        self.push_stack(bus, ((return_point&0xFF00) >> 8) as u8);
+                        self.push_stack(bus, (return_point&0xFF) as u8);
+                        self.pc = addr;
+                    }
+
+                    //ANDs the accumulator with memory
+                    //(modifies zero and negative flag)
+                    Op::ANDIndirectX | Op::ANDZeroPage | Op::ANDImmediate | Op::ANDAbsolute |
+                    Op::ANDIndirectY | Op::ANDZeroPageX | Op::ANDAbsoluteY | Op::ANDAbsoluteX => {
+                        let val = self.read_operand(bus, &opcode);
+                        let a = self.a;
+                        let val = self.and(a, val);
 
-                        self.offset_pc(2);
+                        self.set_a(val);
                     }
 
-                    //Transfer A to Y-index
-                    Op::TAYImplied => {
+                    //Tests accumulator against memory, copying bits 6 and 7 into V and N
+                    Op::BITZeroPage | Op::BITAbsolute => {
+                        let val = self.read_operand(bus, &opcode);
                         let a = self.a;
-                        self.set_y(a);
 
-                        self.offset_pc(1);
+                        self.bit_test(a, val);
                     }
 
-                    //Loads operand into accumulator (modifies zero and negatives flags)
-                    Op::LDAImmediate => {
-                        self.set_a(opcode.imm1());
+                    //Rotates left through the carry flag
+                    Op::ROLZeroPage | Op::ROLAbsolute | Op::ROLZeroPageX | Op::ROLAbsoluteX => {
+                        self.modify_operand(bus, &opcode, NESCpu::rotate_left);
+                    }
+
+                    //Pulls the status register (the break bit is dropped and the unused bit stays set)
+                    Op::PLPImplied => {
+                        let s = self.s;
+                        self.dummy_read(bus, 0x100 + s as u16);
+
+                        let p = self.pop_stack(bus);
 
-                        self.offset_pc(2);
+                        self.set_p_pulled(p);
                     }
 
-                    Op::LDAZeroPage => {
-                        let val = interconnect.read_zero_page(opcode.imm1() as usize);
+                    Op::ROLAccumulator => {
+                        let a = self.a;
+                        self.a = self.rotate_left(a);
+                    }
 
-                        self.set_a(val);
+                    //Branch if Minus (adds to the program counter if negative flag is set)
+                    Op::BMIRelative => {
+                        if self.p.negative == true {
+                            extra_cycles = self.branch(bus, &opcode);
+                        }
+                    }
+
+                    //Sets the Carry flag
+                    Op::SECImplied => {
+                        self.p.set_carry(true);
+                    }
+
+                    //Return from interrupt (pulls status register, then program counter)
+                    Op::RTIImplied => {
+                        let s = self.s;
+                        self.dummy_read(bus, 0x100 + s as u16);
+
+                        let p = self.pop_stack(bus);
+                        self.set_p_pulled(p);
 
-                        self.offset_pc(2);
+                        let lo = self.pop_stack(bus) as u16;
+                        let hi = self.pop_stack(bus) as u16;
+
+                        self.pc = (hi << 8) | lo;
                     }
 
-                    //Loads operand into accumulator using absolute addressing
-                    //(modifies zero and negatives flags)
-                    Op::LDAAbsolute => {
-                        let val = interconnect.read_absolute(opcode.abs_addr());
+                    //Exclusive ORs the accumulator with memory
+                    //(modifies zero and negative flag)
+                    Op::EORIndirectX | Op::EORZeroPage | Op::EORImmediate | Op::EORAbsolute |
+                    Op::EORIndirectY | Op::EORZeroPageX | Op::EORAbsoluteY | Op::EORAbsoluteX => {
+                        let val = self.read_operand(bus, &opcode);
+                        let a = self.a;
+                        let val = self.xor(a, val);
 
                         self.set_a(val);
+                    }
 
-                        self.offset_pc(3);
+                    //Shifts right, bit 0 goes into the carry flag
+                    Op::LSRZeroPage | Op::LSRAbsolute | Op::LSRZeroPageX | Op::LSRAbsoluteX => {
+                        self.modify_operand(bus, &opcode, NESCpu::shift_right);
                     }
 
-                    //Branch if carry set (adds to the program counter if carry flag is set)
-                    Op::BCSRelative => {
-                        if self.p.carry == true {
-                            print!("Branching from 0x{:04X}", self.pc);
-                            self.offset_pc(opcode.imm1().cast_with_neg());
-                            println!(" to 0x{:04X} (PC + 0x{:02X})", self.pc + 2, opcode.imm1().cast_with_neg());
+                    //Pushes the accumulator
+                    Op::PHAImplied => {
+                        let a = self.a;
+                        self.push_stack(bus, a);
+                    }
+
+                    Op::LSRAccumulator => {
+                        let a = self.a;
+                        self.a = self.shift_right(a);
+                    }
+
+                    Op::JMPAbsolute => {
+                        self.pc = opcode.abs_addr() as u16;
+                    }
+
+                    //Branch if overflow clear
+                    Op::BVCRelative => {
+                        if self.p.overflow == false {
+                            extra_cycles = self.branch(bus, &opcode);
                         }
+                    }
 
-                        self.offset_pc(2);
+                    //Clear Interrupt Disable (Sets the I flag to false)
+                    Op::CLIImplied => {
+                        self.p.set_irq_disable(false);
                     }
 
-                    //Loads value into y-index from zero page x memory
-                    Op::LDYZeroPageX => {
-                        let val = interconnect.read_zero_paged_indexed_x(opcode.imm1() as usize, self.x as usize);
+                    //Pulls return point from stack, then sets pc to it plus one
+                    Op::RTSImplied => {
+                        let s = self.s;
+                        self.dummy_read(bus, 0x100 + s as u16);
+
+                        let lo = self.pop_stack(bus) as u16;
+                        let hi = self.pop_stack(bus) as u16;
 
-                        self.set_y(val);
+                        let ret = (hi << 8) | lo;
+                        self.dummy_read(bus, ret);
 
-                        self.offset_pc(2);
+                        self.pc = ret.wrapping_add(1);
                     }
 
-                    //Loads value into accumulator from zero page x memory
-                    Op::LDAZeroPageX => {
-                        let val = interconnect.read_zero_paged_indexed_x(opcode.imm1() as usize, self.x as usize);
+                    //Add with carry (modifies carry, zero, overflow and negative flags)
+                    Op::ADCIndirectX | Op::ADCZeroPage | Op::ADCImmediate | Op::ADCAbsolute |
+                    Op::ADCIndirectY | Op::ADCZeroPageX | Op::ADCAbsoluteY | Op::ADCAbsoluteX => {
+                        let val = self.read_operand(bus, &opcode);
+                        let a = self.a;
+                        let val = self.add_with_carry(a, val);
 
                         self.set_a(val);
+                    }
 
-                        self.offset_pc(2);
+                    //Rotates right through the carry flag
+                    Op::RORZeroPage | Op::RORAbsolute | Op::RORZeroPageX | Op::RORAbsoluteX => {
+                        self.modify_operand(bus, &opcode, NESCpu::rotate_right);
                     }
 
-                    Op::LDAAbsoluteY => {
-                        let val = interconnect.read_absolute_indexed_y(opcode.abs_addr(), self.y as usize);
+                    //Pulls the accumulator (modifies zero and negative flags)
+                    Op::PLAImplied => {
+                        let s = self.s;
+                        self.dummy_read(bus, 0x100 + s as u16);
+
+                        let val = self.pop_stack(bus);
 
                         self.set_a(val);
+                    }
 
-                        self.offset_pc(3);
+                    Op::RORAccumulator => {
+                        let a = self.a;
+                        self.a = self.rotate_right(a);
                     }
 
-                    //Loads operand into accumulator using absolute indexed addressing
-                    //(modifies zero and negatives flags)
-                    Op::LDAAbsoluteX => {
-                        let val = interconnect.read_absolute_indexed_x(opcode.abs_addr(), self.x as usize);
-                        
-                        self.set_a(val);
+                    Op::JMPAbsIndirect => {
+                        let (addr, _) = self.resolve_address(bus, &opcode, Access::Read);
+                        self.pc = addr;
+                    }
 
-                        self.offset_pc(3);
+                    //Branch if overflow set
+                    Op::BVSRelative => {
+                        if self.p.overflow == true {
+                            extra_cycles = self.branch(bus, &opcode);
+                        }
                     }
 
-                    //Compare y-index with operand
-                    //(modifies carry, zero, and negative flags)
-                    Op::CPYImmediate => {
+                    //Set Interrupt Disable (Sets the I flag to true)
+                    Op::SEIImplied => {
+                        self.p.set_irq_disable(true);
+                    }
+
+                    //Store Accumulator (Stores a into memory)
+                    Op::STAIndirectX | Op::STAZeroPage | Op::STAAbsolute | Op::STAIndirectY |
+                    Op::STAZeroPageX | Op::STAAbsoluteY | Op::STAAbsoluteX => {
+                        let a = self.a;
+                        self.write_operand(bus, &opcode, a);
+                    }
+
+                    //Store y-index into memory
+                    Op::STYZeroPage | Op::STYAbsolute | Op::STYZeroPageX => {
                         let y = self.y;
-                        let _ = self.subtract(y, opcode.imm1());
+                        self.write_operand(bus, &opcode, y);
+                    }
 
-                        self.offset_pc(2);
+                    //Store x-index into memory
+                    Op::STXZeroPage | Op::STXAbsolute | Op::STXZeroPageY => {
+                        let x = self.x;
+                        self.write_operand(bus, &opcode, x);
                     }
 
-                    Op::INYImplied => {
-                        self.offset_y(1);
+                    //Decrements the y-index
+                    //(modifies zero and negative flags)
+                    Op::DEYImplied => {
+                        self.offset_y(0xFF);
+                    }
 
-                        self.offset_pc(1);
+                    //Transfer X-index to A
+                    Op::TXAImplied => {
+                        let x = self.x;
+                        self.set_a(x);
                     }
 
-                    //Compare accumulator with operand
-                    //(modifies carr, zero, and negative flags)
-                    Op::CMPImmediate => {
+                    //Branch if carry clear (adds to the program counter if carry flag is clear)
+                    Op::BCCRelative => {
+                        if self.p.carry == false {
+                            extra_cycles = self.branch(bus, &opcode);
+                        }
+                    }
+
+                    //Transfer Y-index to A
+                    Op::TYAImplied => {
+                        let y = self.y;
+                        self.set_a(y);
+                    }
+
+                    //Transfers x-index into stack pointer
+                    Op::TXSImplied => {
+                        self.s = self.x;
+                    }
+
+                    //Loads operand into y-index (modifies zero and negatives flags)
+                    Op::LDYImmediate | Op::LDYZeroPage | Op::LDYAbsolute | Op::LDYZeroPageX |
+                    Op::LDYAbsoluteX => {
+                        let val = self.read_operand(bus, &opcode);
+
+                        self.set_y(val);
+                    }
+
+                    //Loads operand into accumulator (modifies zero and negatives flags)
+                    Op::LDAIndirectX | Op::LDAZeroPage | Op::LDAImmediate | Op::LDAAbsolute |
+                    Op::LDAIndirectY | Op::LDAZeroPageX | Op::LDAAbsoluteY | Op::LDAAbsoluteX => {
+                        let val = self.read_operand(bus, &opcode);
+
+                        self.set_a(val);
+                    }
+
+                    //Loads operand into x-index (modifies zero and negatives flags)
+                    Op::LDXImmediate | Op::LDXZeroPage | Op::LDXAbsolute | Op::LDXZeroPageY |
+                    Op::LDXAbsoluteY => {
+                        let val = self.read_operand(bus, &opcode);
+
+                        self.set_x(val);
+                    }
+
+                    //Transfer A to Y-index
+                    Op::TAYImplied => {
                         let a = self.a;
-                        let _ = self.subtract(a, opcode.imm1());
+                        self.set_y(a);
+                    }
 
-                        self.offset_pc(2);
+                    //Transfer A to X-index
+                    Op::TAXImplied => {
+                        let a = self.a;
+                        self.set_x(a);
                     }
-                    
-                    //Decrements the x-index
-                    //(modifies zero and negative flags)
-                    Op::DEXImplied => {
-                        self.offset_x(0xFF);
 
-                        self.offset_pc(1);
+                    //Branch if carry set (adds to the program counter if carry flag is set)
+                    Op::BCSRelative => {
+                        if self.p.carry == true {
+                            extra_cycles = self.branch(bus, &opcode);
+                        }
+                    }
+
+                    //Clears the Overflow flag
+                    Op::CLVImplied => {
+                        self.p.set_overflow(false);
+                    }
+
+                    //Transfers stack pointer into x-index (modifies zero and negative flags)
+                    Op::TSXImplied => {
+                        let s = self.s;
+                        self.set_x(s);
                     }
 
-                    Op::CMPAbsolute => {
+                    //Compare y-index with operand
+                    //(modifies carry, zero, and negative flags)
+                    Op::CPYImmediate | Op::CPYZeroPage | Op::CPYAbsolute => {
+                        let val = self.read_operand(bus, &opcode);
+                        let y = self.y;
+                        let _ = self.subtract(y, val);
+                    }
+
+                    //Compare accumulator with operand
+                    //(modifies carry, zero, and negative flags)
+                    Op::CMPIndirectX | Op::CMPZeroPage | Op::CMPImmediate | Op::CMPAbsolute |
+                    Op::CMPIndirectY | Op::CMPZeroPageX | Op::CMPAbsoluteY | Op::CMPAbsoluteX => {
+                        let val = self.read_operand(bus, &opcode);
                         let a = self.a;
-                        let val = interconnect.read_absolute(opcode.abs_addr() as usize);
                         let _ = self.subtract(a, val);
+                    }
+
+                    //Decrements memory (modifies zero and negative flags)
+                    Op::DECZeroPage | Op::DECAbsolute | Op::DECZeroPageX | Op::DECAbsoluteX => {
+                        self.modify_operand(bus, &opcode, NESCpu::decrement);
+                    }
+
+                    //Increments the y-index
+                    //(modifies zero and negative flags)
+                    Op::INYImplied => {
+                        self.offset_y(1);
+                    }
 
-                        self.offset_pc(3);
+                    //Decrements the x-index
+                    //(modifies zero and negative flags)
+                    Op::DEXImplied => {
+                        self.offset_x(0xFF);
                     }
-                    
+
                     //Branch if not equal (adds to the program counter if zero flag is not set)
                     Op::BNERelative => {
                         if self.p.zero == false {
-                            print!("Branching from 0x{:04X}", self.pc);
-                            self.offset_pc(opcode.imm1().cast_with_neg());
-                            println!(" to 0x{:04X} (PC + 0x{:02X})", self.pc + 2, opcode.imm1().cast_with_neg());
+                            extra_cycles = self.branch(bus, &opcode);
                         }
-
-                        self.offset_pc(2);
                     }
 
                     //Clear Decimal Mode (Sets the D flag to false)
                     Op::CLDImplied => {
                         self.p.set_decimal(false);
-
-                        self.offset_pc(1);
                     }
 
-                    Op::CPXImmediate => {
+                    //Compare x-index with operand
+                    //(modifies carry, zero, and negative flags)
+                    Op::CPXImmediate | Op::CPXZeroPage | Op::CPXAbsolute => {
+                        let val = self.read_operand(bus, &opcode);
                         let x = self.x;
-                        let _ = self.subtract(x, opcode.imm1());
+                        let _ = self.subtract(x, val);
+                    }
+
+                    //Subtract with carry (carry flag is the inverse of borrow)
+                    Op::SBCIndirectX | Op::SBCZeroPage | Op::SBCImmediate | Op::SBCAbsolute |
+                    Op::SBCIndirectY | Op::SBCZeroPageX | Op::SBCAbsoluteY | Op::SBCAbsoluteX => {
+                        let val = self.read_operand(bus, &opcode);
+                        let a = self.a;
+                        let val = self.subtract_with_carry(a, val);
 
-                        self.offset_pc(2);
+                        self.set_a(val);
                     }
 
-                    Op::CPXZeroPage => {
-                        let val = interconnect.read_zero_page(opcode.imm1() as usize);
-                        let x = self.x;
-                        let _ = self.subtract(x, val);
+                    //Increments memory (modifies zero and negative flags)
+                    Op::INCZeroPage | Op::INCAbsolute | Op::INCZeroPageX | Op::INCAbsoluteX => {
+                        self.modify_operand(bus, &opcode, NESCpu::increment);
+                    }
 
-                        self.offset_pc(2);
+                    //Increments the x-index
+                    //(modifies zero and negative flags)
+                    Op::INXImplied => {
+                        self.offset_x(1);
                     }
 
-                    Op::CPXAbsolute => {
-                        let x = self.x;
-                        let val = interconnect.read_absolute(opcode.abs_addr() as usize);
-                        let _ = self.subtract(x, val);
+                    Op::NOPImplied => {
 
-                        self.offset_pc(3);
                     }
 
+                    //Branch if equal (adds to the program counter if zero flag is set)
                     Op::BEQRelative => {
                         if self.p.zero == true {
-                            print!("Branching from 0x{:04X}", self.pc);
-                            self.offset_pc(opcode.imm1().cast_with_neg());
-                            println!(" to 0x{:04X} (PC + 0x{:02X})", self.pc + 2, opcode.imm1().cast_with_neg());
+                            extra_cycles = self.branch(bus, &opcode);
                         }
-
-                        self.offset_pc(2);
                     }
 
-                    _ => {
-                        println!("unimplemented opcode");
-                        return false
+                    //Set Decimal Mode (Sets the D flag to true)
+                    Op::SEDImplied => {
+                        self.p.set_decimal(true);
                     }
                 }
             }
             
+            //Anything that isn't an official opcode
             None => {
-                println!("unknown {:?}", opcode);
-                return false
+                if !self.unofficial_opcodes {
+                    self.pc = pc;
+                    return Err(CpuError::UnofficialOpcode { pc: pc, opcode: opcode })
+                }
+
+                if let Err(err) = self.do_unofficial(bus, &opcode) {
+                    self.pc = pc;
+                    return Err(err)
+                }
             }
         }
         
-        println!("{:?}", opcode);
+        //Reads indexed into the next page take an extra cycle to fix up the high byte
+        if self.page_crossed && opcode.info().page_penalty {
+            extra_cycles += 1;
+        }
+
+        let cycles = self.finish_step(bus, start_cycles, opcode.info().cycles + extra_cycles);
+
+        let irq_disable = match opcode.op() {
+            0x58 | 0x78 | 0x28 => irq_disable,
+            _ => self.p.irq_disable,
+        };
+        self.poll_interrupts(bus, irq_disable);
+
+        Ok(cycles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::bus::{BusCycle, FlatBus};
+
+    //Loads a program at $0400 and points the cpu at it
+    fn setup(mode: ExecutionMode, program: &[u8]) -> (NESCpu, FlatBus) {
+        let mut bus = FlatBus::new();
+        bus.ram[0x0400..(0x0400 + program.len())].copy_from_slice(program);
+
+        let mut cpu = NESCpu::with_mode(mode);
+        cpu.set_pc(0x0400);
+        cpu.set_s(0xFD);
+
+        (cpu, bus)
+    }
+
+    //FlatBus with interrupt lines the test can pull
+    struct LineBus {
+        bus: FlatBus,
+        nmi: bool,
+        irq: bool,
+    }
+
+    impl Bus for LineBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.bus.read(addr)
+        }
+        fn write(&mut self, addr: u16, val: u8) {
+            self.bus.write(addr, val)
+        }
+        fn nmi_line(&self) -> bool {
+            self.nmi
+        }
+        fn irq_line(&self) -> bool {
+            self.irq
+        }
+    }
 
-        println!("a: 0x{:02X}\nx: 0x{:02X}\ny: 0x{:02X}\ns: 0x{:02X}\n{:?}\n\n", 
-                    self.a, self.x, self.y,
-                    self.s, self.p);
+    #[test]
+    fn status_round_trips_every_byte() {
+        for val in 0u16..0x100 {
+            let val = val as u8;
+            assert_eq!(CPUStatus::from(val).to_u8(), val);
+        }
+    }
 
-        true
+    #[test]
+    fn php_and_brk_push_the_break_bit_set() {
+        //PHP
+        let (mut cpu, mut bus) = setup(ExecutionMode::CycleStepped, &[0x08]);
+        cpu.set_p_u8(0x00);
+        cpu.do_instruction(&mut bus).unwrap();
+        assert_eq!(bus.ram[0x01FD], 0x30);
+
+        //BRK, pushing pc before the status
+        let (mut cpu, mut bus) = setup(ExecutionMode::CycleStepped, &[0x00, 0x00]);
+        cpu.set_p_u8(0x00);
+        cpu.do_instruction(&mut bus).unwrap();
+        assert_eq!(bus.ram[0x01FB], 0x30);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn nmi_and_irq_push_the_break_bit_clear() {
+        for &(nmi, p, pushed) in &[(true, 0xFF, 0xEF), (false, 0xFB, 0xEB)] {
+            //NOP, then the interrupt polled at its end
+            let (mut cpu, bus) = setup(ExecutionMode::CycleStepped, &[0xEA]);
+            let mut bus = LineBus { bus: bus, nmi: nmi, irq: !nmi };
+            cpu.set_p_u8(p);
+
+            cpu.do_instruction(&mut bus).unwrap();
+            cpu.do_instruction(&mut bus).unwrap();
+            assert_eq!(bus.bus.ram[0x01FB], pushed, "nmi: {}", nmi);
+        }
+    }
+
+    #[test]
+    fn plp_and_rti_ignore_the_break_bit() {
+        for &(pulled, p) in &[(0x10, 0x20), (0xDF, 0xEF), (0xFF, 0xEF)] {
+            //PLP
+            let (mut cpu, mut bus) = setup(ExecutionMode::CycleStepped, &[0x28]);
+            bus.ram[0x01FE] = pulled;
+            cpu.do_instruction(&mut bus).unwrap();
+            assert_eq!(cpu.p().to_u8(), p);
+
+            //RTI, pulling the status before pc
+            let (mut cpu, mut bus) = setup(ExecutionMode::CycleStepped, &[0x40]);
+            bus.ram[0x01FE] = pulled;
+            cpu.do_instruction(&mut bus).unwrap();
+            assert_eq!(cpu.p().to_u8(), p);
+        }
+    }
+
+    #[test]
+    fn read_modify_write_writes_the_old_value_first() {
+        for &mode in &[ExecutionMode::InstructionStepped, ExecutionMode::CycleStepped] {
+            //INC $10
+            let (mut cpu, mut bus) = setup(mode, &[0xE6, 0x10]);
+            bus.ram[0x10] = 0xFF;
+            bus.log = Some(Vec::new());
+
+            cpu.do_instruction(&mut bus).unwrap();
+
+            let writes: Vec<BusCycle> = bus.log.unwrap().into_iter().filter(|cycle| cycle.write).collect();
+            assert_eq!(writes, vec![
+                BusCycle { addr: 0x10, val: 0xFF, write: true },
+                BusCycle { addr: 0x10, val: 0x00, write: true },
+            ], "{:?}", mode);
+        }
+    }
+}
@@ -1,9 +1,9 @@
-use minifb::{WindowOptions, Window, Key, Scale};
-
 use super::ppu::*;
 use super::apu::*;
 use super::io::*;
 use super::cart::*;
+use super::irq::*;
+use super::bus::Bus;
 
 /*
 This struct is really only used to pass the RAM and
@@ -18,70 +18,101 @@ pub struct Interconnect {
     io: NESIo,
 
     cart: NESCart,
+
+    //The console's 2KB of nametable VRAM (the second 2KB is only there for four-screen carts)
+    ciram: [u8; 0x1000],
+
+    irq: IrqLine,
+
+    frame_ready: bool,
 }
 
 impl Interconnect {
     pub fn new() -> Interconnect {
+        let irq = IrqLine::new();
+
         Interconnect {
             internal_ram: [0u8;0x0800],
             
             ppu: NESPpu::new(),
-            apu: NESApu::new(),
+            apu: NESApu::new(irq.clone()),
             io: NESIo::new(),
 
             cart: NESCart::none(),
+
+            ciram: [0u8; 0x1000],
+
+            irq: irq,
+
+            frame_ready: false,
         }
     }
 
-    pub fn insert_cart(&mut self, rom: &str) {
-        self.cart = NESCart::new(rom);
+    pub fn insert_cart(&mut self, rom: &str) -> Result<(), CartError> {
+        self.cart = NESCart::new(rom, self.irq.clone())?;
 
         self.cart.print_header();
+
+        //Only NTSC timing is emulated so far, so PAL and Dendy games run at
+        //the wrong speed. Multi-region games are fine with it
+        match self.cart.header().map(|header| header.tv_system) {
+            Some(tv_system @ TvSystem::Pal) | Some(tv_system @ TvSystem::Dendy) => {
+                println!("warning: this game expects {:?} timing, but it will run at NTSC timing", tv_system);
+            }
+            _ => {}
+        }
+
+        Ok(())
     }
 
     pub fn ram(&self) -> [u8;0x0800] {
         self.internal_ram
     }
 
-    pub fn get_pattern_table(&mut self, num: usize) -> &[u8] {
-        match num {
-            0...3 => {
-                self.cart.get_pattern_table(num)
-            }
+    //Clocks the rest of the system for a single cpu cycle,
+    //the PPU runs three dots for every cpu cycle
+    pub fn tick(&mut self) {
+        self.apu.tick();
+        self.cart.cpu_clock();
 
-            _ => {
-                &[0;0x1000]
+        for _ in 0..3 {
+            if self.ppu.step(&mut VideoBus::new(&mut self.cart, &mut self.ciram)) {
+                self.frame_ready = true;
             }
         }
     }
 
-    pub fn get_name_table(&mut self, num: usize) -> &[u8] {
-        match num {
-            0...3 => {
-                &self.internal_ram[(num*0x400)..((num+1)*0x400)]
-            }
-
-            _ => {
-                &[0;0x400]
-            }
+    //Catches the rest of the system up with the cpu
+    pub fn update(&mut self, cpu_cycles: u8) {
+        for _ in 0..cpu_cycles {
+            self.tick();
         }
     }
 
-    pub fn update(&mut self, window: &mut Window) {
-        let mut pt0 = self.cart.get_pattern_table(0);
-        let mut pt1 = self.cart.get_pattern_table(1);
+    //(scanline, dot) the PPU is about to draw
+    pub fn ppu_position(&self) -> (i16, u16) {
+        (self.ppu.scanline(), self.ppu.dot())
+    }
+
+    pub fn nmi_line(&self) -> bool {
+        self.ppu.nmi_line()
+    }
+
+    pub fn irq_line(&self) -> bool {
+        self.irq.is_asserted()
+    }
 
-        let mut nt0 = &self.internal_ram[(0*0x400)..((0+1)*0x400)];
-        let mut nt1 = &self.internal_ram[(1*0x400)..((1+1)*0x400)];
+    //Returns true once for every frame the PPU has finished
+    pub fn poll_frame(&mut self) -> bool {
+        let ready = self.frame_ready;
+        self.frame_ready = false;
 
-        self.ppu.do_cycle(pt0, pt1, nt0, nt1, window);
+        ready
     }
 
     //Reading Memory
-    
-    //BIG TODO: This will probably need to be /totally/ overhauled
-    //in order for ROMS with different mappers to work; so for now
-    //we are just going to hardcode mapper 000
+
+    //Everything from $4020 up belongs to the cart's mapper
     //https://wiki.nesdev.com/w/index.php/CPU_memory_map
     pub fn read_mem(&mut self, addr: usize) -> u8 {
         match addr {
@@ -90,7 +121,11 @@ impl Interconnect {
             }
 
             0x2000...0x3FFF => {
-                self.ppu.read_ppu((addr - 0x2000) % 8)
+                self.ppu.read_ppu((addr - 0x2000) % 8, &mut VideoBus::new(&mut self.cart, &mut self.ciram))
+            }
+
+            0x4015 => {
+                self.apu.read_status()
             }
 
             0x4000...0x4017 => {
@@ -101,20 +136,8 @@ impl Interconnect {
                 0 //do we need this?
             }
 
-            0x4020...0x5FFF => {
-                0 //self.cart.read(addr - 0x4010)
-            }
-
-            0x6000...0x7FFF => {
-                self.cart.read_ram(addr - 0x6000)
-            }
-
-            0x8000...0xBFFF => {
-                self.cart.read(addr - (0x8000 - 0x10))
-            }
-
-            0xC000...0xFFFF => {
-                self.cart.read(addr - (0xC000 - 0x10))
+            0x4020...0xFFFF => {
+                self.cart.cpu_read(addr as u16)
             }
 
             _ => {
@@ -124,45 +147,26 @@ impl Interconnect {
         }
     }
 
-    pub fn read_zero_page(&mut self, addr: usize) -> u8 {
-        self.read_mem(addr % 256)
-    }
-
-    pub fn read_absolute(&mut self, addr: usize) -> u8 {
-        self.read_mem(addr)
-    }
-
-    pub fn read_zero_paged_indexed_x(&mut self, addr: usize, x: usize) -> u8 {
-        self.read_mem((addr + x) % 256)
-    }
-
-    pub fn read_zero_paged_indexed_y(&mut self, addr: usize, y: usize) -> u8 {
-        self.read_mem((addr + y) % 256)
-    }
-
-    pub fn read_absolute_indexed_x(&mut self, addr: usize, x: usize) -> u8 {
-        self.read_mem((addr + x))
-    }
-
-    pub fn read_absolute_indexed_y(&mut self, addr: usize, y: usize) -> u8 {
-        self.read_mem((addr + y))
-    }
+    //Reads memory without any of the side effects a real read would have,
+    //for debuggers and logs. The PPU, APU and I/O registers can't be peeked
+    //since reading them for real would change their state
+    pub fn peek_mem(&self, addr: usize) -> Option<u8> {
+        match addr {
+            0x0000...0x1FFF => {
+                Some(self.internal_ram[addr % 0x0800])
+            }
 
-    pub fn read_indexed_indirect_x(&mut self, addr: usize, x: usize) -> u8 {
-        let pointer: usize = self.read_mem((addr + x) % 256) as usize + self.read_mem((addr + x + 1) % 256) as usize * 256;
-        self.read_mem(pointer)
-    }
+            0x4020...0xFFFF => {
+                self.cart.cpu_peek(addr as u16)
+            }
 
-    pub fn read_indexed_indirect_y(&mut self, addr: usize, y: usize) -> u8 {
-        let pointer: usize = self.read_mem((addr + y) % 256) as usize + self.read_mem((addr + y + 1) % 256) as usize * 256;
-        self.read_mem(pointer)
+            _ => {
+                None
+            }
+        }
     }
 
     //Writing Memory
-
-    //BIG TODO: This will probably need to be /totally/ overhauled
-    //in order for ROMS with different mappers to work; so for now
-    //we are just going to hardcode mapper 000
     pub fn write_mem(&mut self, addr: usize, val: u8) {
         match addr {
             0x0000...0x1FFF => {
@@ -172,71 +176,119 @@ impl Interconnect {
             0x2000...0x3FFF => {
                 //TODO: write to ppu registers
                 //println!("Unimplemented Write to PPU registers!");
-                self.ppu().write_ppu((addr - 0x2000) % 8, val);
+                self.cart.ppu_register_write(0x2000 + (addr % 8) as u16, val);
+                self.ppu.write_ppu((addr - 0x2000) % 8, val, &mut VideoBus::new(&mut self.cart, &mut self.ciram));
             }
 
-            0x4000...0x4017 => {
+            0x4017 => {
+                self.apu.write_frame_counter(val);
+            }
+
+            0x4000...0x4016 => {
                 //TODO: write to apu and i/o registers
-                println!("Unimplemented Write to I/O registers!");
             }
 
             0x4018...0x401F => {
                 //do we need this?
             }
 
-            0x4020...0x5FFF => {
-                
+            0x4020...0xFFFF => {
+                self.cart.cpu_write(addr as u16, val);
             }
 
-            0x6000...0x7FFF => {
-                self.cart.write_ram(addr - 0x6000, val)
+            _ => {
+                println!("shouldn't get here, as the above code is exhaustive");
             }
+        };
+    }
 
-            0x8000...0xFFFF => {
-                println!("tried to write to cart rom!");
+    pub fn ppu(&mut self) -> &mut NESPpu {
+        &mut self.ppu
+    }
+}
+
+//What the PPU sees on its own bus: the pattern tables are on the cart,
+//and the nametables are the console's VRAM arranged by the cart's mirroring.
+//Palette RAM lives in the PPU itself
+//https://wiki.nesdev.com/w/index.php/PPU_memory_map
+pub struct VideoBus<'a> {
+    cart: &'a mut NESCart,
+    ciram: &'a mut [u8; 0x1000],
+}
+
+impl<'a> VideoBus<'a> {
+    pub fn new(cart: &'a mut NESCart, ciram: &'a mut [u8; 0x1000]) -> VideoBus<'a> {
+        VideoBus {
+            cart: cart,
+            ciram: ciram,
+        }
+    }
+
+    pub fn read(&mut self, addr: u16) -> u8 {
+        match addr & 0x3FFF {
+            addr @ 0x0000...0x1FFF => {
+                self.cart.ppu_read(addr)
             }
 
-            _ => {
-                println!("shouldn't get here, as the above code is exhaustive");
+            //$3000-$3EFF mirrors the nametables
+            addr => {
+                self.cart.read_nametable(addr, self.ciram)
             }
-        };
+        }
     }
 
-    pub fn write_zero_page(&mut self, addr: usize, val: u8) {
-        self.write_mem(addr % 256, val);
+    //A pattern table fetch made while rendering. The cart sees the address
+    //after the byte comes back, so a board switching banks on it
+    //(like the MMC2's latches) only affects the fetches after it
+    pub fn fetch(&mut self, addr: u16) -> u8 {
+        let val = self.read(addr);
+        self.cart.ppu_address(addr);
+        val
     }
 
-    pub fn write_absolute(&mut self, addr: usize, val: u8) {
-        self.write_mem(addr, val);
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr & 0x3FFF {
+            addr @ 0x0000...0x1FFF => {
+                self.cart.ppu_write(addr, val);
+            }
+
+            addr => {
+                self.cart.write_nametable(addr, val, self.ciram);
+            }
+        }
+    }
+}
+
+impl Bus for Interconnect {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.read_mem(addr as usize)
     }
 
-    pub fn write_zero_paged_indexed_x(&mut self, addr: usize, x: usize, val: u8) {
-        self.write_mem((addr + x) % 256, val);
+    fn write(&mut self, addr: u16, val: u8) {
+        self.write_mem(addr as usize, val);
     }
 
-    pub fn write_zero_paged_indexed_y(&mut self, addr: usize, y: usize, val: u8) {
-        self.write_mem((addr + y) % 256, val);
+    fn peek(&self, addr: u16) -> Option<u8> {
+        self.peek_mem(addr as usize)
     }
 
-    pub fn write_absolute_indexed_x(&mut self, addr: usize, x: usize, val: u8) {
-        self.write_mem((addr + x), val);
+    fn tick(&mut self) {
+        Interconnect::tick(self);
     }
 
-    pub fn write_absolute_indexed_y(&mut self, addr: usize, y: usize, val: u8) {
-        self.write_mem((addr + y), val);
+    fn update(&mut self, cpu_cycles: u8) {
+        Interconnect::update(self, cpu_cycles);
     }
 
-    pub fn write_indexed_indirect_x(&mut self, addr: usize, x: usize, val: u8) {
-        let pointer: usize = self.read_mem((addr + x) % 256) as usize + self.read_mem((addr + x + 1) % 256) as usize * 256;
-        self.write_mem(pointer, val);
+    fn nmi_line(&self) -> bool {
+        Interconnect::nmi_line(self)
     }
 
-    pub fn write_indexed_indirect_y(&mut self, addr: usize, y: usize, val: u8) {
-        let pointer: usize = self.read_mem((addr + y) % 256) as usize + self.read_mem((addr + y + 1) % 256) as usize * 256;
-        self.write_mem(pointer, val);
+    fn irq_line(&self) -> bool {
+        Interconnect::irq_line(self)
     }
 
-    pub fn ppu(&mut self) -> &mut NESPpu {
-        &mut self.ppu
+    fn ppu_position(&self) -> (i16, u16) {
+        Interconnect::ppu_position(self)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,42 @@
+use std::rc::Rc;
+use std::cell::Cell;
+
+//Everything on the board that can pull the cpu's /IRQ line low
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IrqSource {
+    FrameCounter = 0x1,
+    Dmc = 0x2,
+    Mapper = 0x4,
+}
+
+//The /IRQ line is wired-OR, so it stays asserted for as long as any source
+//is holding it. Cloning hands out another connection to the same line
+//https://wiki.nesdev.com/w/index.php/IRQ
+#[derive(Clone)]
+pub struct IrqLine {
+    sources: Rc<Cell<u8>>,
+}
+
+impl IrqLine {
+    pub fn new() -> IrqLine {
+        IrqLine {
+            sources: Rc::new(Cell::new(0)),
+        }
+    }
+
+    pub fn assert(&self, source: IrqSource) {
+        self.sources.set(self.sources.get() | source as u8);
+    }
+
+    pub fn release(&self, source: IrqSource) {
+        self.sources.set(self.sources.get() & !(source as u8));
+    }
+
+    pub fn is_asserted(&self) -> bool {
+        self.sources.get() != 0
+    }
+
+    pub fn is_asserted_by(&self, source: IrqSource) -> bool {
+        (self.sources.get() & source as u8) != 0
+    }
+}
@@ -0,0 +1,329 @@
+use std::fmt;
+
+//Just enough JSON to read test suites with. Numbers are kept as f64,
+//which holds anything a 6502 test needs exactly
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+#[derive(Debug)]
+pub struct JsonError {
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.message, self.offset)
+    }
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, JsonError> {
+        let mut parser = Parser { bytes: text.as_bytes(), pos: 0 };
+
+        let value = parser.value()?;
+
+        parser.skip_whitespace();
+        if parser.pos != parser.bytes.len() {
+            return Err(parser.error("trailing characters"))
+        }
+
+        Ok(value)
+    }
+
+    //Looks up a key in an object
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref members) => members.iter().find(|m| m.0 == key).map(|m| &m.1),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match *self {
+            Json::Array(ref values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Json::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Json::Number(n) if n >= 0.0 && n.fract() == 0.0 => Some(n as u64),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &'static str) -> JsonError {
+        JsonError { offset: self.pos, message: message }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.bytes.len() && (self.bytes[self.pos] as char).is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.bytes.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), JsonError> {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error("unexpected character"))
+        }
+    }
+
+    fn literal(&mut self, text: &str, value: Json) -> Result<Json, JsonError> {
+        if self.bytes[self.pos..].starts_with(text.as_bytes()) {
+            self.pos += text.len();
+            Ok(value)
+        } else {
+            Err(self.error("unknown literal"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError> {
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b'-') | Some(b'0'...b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, JsonError> {
+        self.expect(b'{')?;
+
+        let mut members = Vec::new();
+
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(members))
+        }
+
+        loop {
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a key"))
+            }
+
+            let key = self.string()?;
+            self.expect(b':')?;
+            let value = self.value()?;
+            members.push((key, value));
+
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(members))
+                }
+                _ => return Err(self.error("expected , or }")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, JsonError> {
+        self.expect(b'[')?;
+
+        let mut values = Vec::new();
+
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Array(values))
+        }
+
+        loop {
+            values.push(self.value()?);
+
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(values))
+                }
+                _ => return Err(self.error("expected , or ]")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect(b'"')?;
+
+        let mut s = String::new();
+
+        loop {
+            let start = self.pos;
+            while self.pos < self.bytes.len() && self.bytes[self.pos] != b'"' && self.bytes[self.pos] != b'\\' {
+                self.pos += 1;
+            }
+            //Input came from a &str and we only stop on ascii, so this is still valid utf-8
+            s.push_str(&String::from_utf8_lossy(&self.bytes[start..self.pos]));
+
+            match self.bytes.get(self.pos) {
+                Some(&b'"') => {
+                    self.pos += 1;
+                    return Ok(s)
+                }
+
+                Some(&b'\\') => {
+                    let escaped = match self.bytes.get(self.pos + 1) {
+                        Some(&b'"') => '"',
+                        Some(&b'\\') => '\\',
+                        Some(&b'/') => '/',
+                        Some(&b'b') => '\u{8}',
+                        Some(&b'f') => '\u{c}',
+                        Some(&b'n') => '\n',
+                        Some(&b'r') => '\r',
+                        Some(&b't') => '\t',
+                        //Surrogate pairs aren't needed for anything we read
+                        Some(&b'u') => {
+                            let hex = self.bytes.get(self.pos + 2..self.pos + 6)
+                                .and_then(|hex| ::std::str::from_utf8(hex).ok())
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .ok_or(self.error("bad unicode escape"))?;
+                            self.pos += 4;
+
+                            ::std::char::from_u32(hex).unwrap_or('\u{fffd}')
+                        }
+                        _ => return Err(self.error("bad escape")),
+                    };
+
+                    s.push(escaped);
+                    self.pos += 2;
+                }
+
+                _ => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, JsonError> {
+        let start = self.pos;
+
+        while self.pos < self.bytes.len() {
+            match self.bytes[self.pos] {
+                b'-' | b'+' | b'.' | b'e' | b'E' | b'0'...b'9' => self.pos += 1,
+                _ => break,
+            }
+        }
+
+        ::std::str::from_utf8(&self.bytes[start..self.pos]).ok()
+            .and_then(|text| text.parse::<f64>().ok())
+            .map(Json::Number)
+            .ok_or(JsonError { offset: start, message: "bad number" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_values() {
+        let json = Json::parse(r#"{"a": [1, {"b": null}, []], "c": {"d": true, "e": false}}"#).unwrap();
+
+        assert_eq!(json, Json::Object(vec![
+            ("a".to_string(), Json::Array(vec![
+                Json::Number(1.0),
+                Json::Object(vec![("b".to_string(), Json::Null)]),
+                Json::Array(vec![]),
+            ])),
+            ("c".to_string(), Json::Object(vec![
+                ("d".to_string(), Json::Bool(true)),
+                ("e".to_string(), Json::Bool(false)),
+            ])),
+        ]));
+
+        assert_eq!(json.get("c").and_then(|c| c.get("d")), Some(&Json::Bool(true)));
+        assert_eq!(json.get("a").and_then(Json::as_array).map(|a| a.len()), Some(3));
+        assert_eq!(json.get("missing"), None);
+    }
+
+    #[test]
+    fn numbers() {
+        let json = Json::parse("[0, -1, 65535, -0.5, 2e3, -1E-2]").unwrap();
+
+        assert_eq!(json, Json::Array(vec![
+            Json::Number(0.0),
+            Json::Number(-1.0),
+            Json::Number(65535.0),
+            Json::Number(-0.5),
+            Json::Number(2000.0),
+            Json::Number(-0.01),
+        ]));
+    }
+
+    //Only whole, non-negative numbers are integers
+    #[test]
+    fn as_u64() {
+        assert_eq!(Json::parse("65535").unwrap().as_u64(), Some(65535));
+        assert_eq!(Json::parse("-1").unwrap().as_u64(), None);
+        assert_eq!(Json::parse("1.5").unwrap().as_u64(), None);
+        assert_eq!(Json::parse("\"1\"").unwrap().as_u64(), None);
+    }
+
+    #[test]
+    fn string_escapes() {
+        let json = Json::parse(r#""a\"b\\c\/d\b\f\n\r\t\u0041\u00e9 é""#).unwrap();
+
+        assert_eq!(json.as_str(), Some("a\"b\\c/d\u{8}\u{c}\n\r\tAé é"));
+    }
+
+    #[test]
+    fn whitespace_between_tokens() {
+        let json = Json::parse(" \r\n\t{ \"a\" :\n [ 1 ,\t2 ] } \n").unwrap();
+
+        assert_eq!(json.get("a"), Some(&Json::Array(vec![Json::Number(1.0), Json::Number(2.0)])));
+    }
+
+    #[test]
+    fn malformed_input() {
+        let inputs = [
+            "", "   ", "[1,", "[1 2]", "[1,]", "{\"a\" 1}", "{1: 2}", "{\"a\": 1,}",
+            "\"abc", "\"\\x\"", "\"\\u12\"", "tru", "nul", "-", "1-2", "{} {}", "]",
+        ];
+
+        for input in &inputs {
+            assert!(Json::parse(input).is_err(), "{:?} parsed", input);
+        }
+    }
+
+    #[test]
+    fn error_offsets() {
+        let err = Json::parse("[1, x]").unwrap_err();
+        assert_eq!((err.offset, err.message), (4, "unexpected character"));
+
+        let err = Json::parse("[1] 2").unwrap_err();
+        assert_eq!((err.offset, err.message), (4, "trailing characters"));
+    }
+}
@@ -0,0 +1,166 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use super::cpu::*;
+use super::bus::*;
+
+//Where the prebuilt binaries start running and where they trap when every test passed
+//https://github.com/Klaus2m5/6502_65C02_functional_tests
+pub const START: u16 = 0x0400;
+pub const FUNCTIONAL_SUCCESS: u16 = 0x3469;
+pub const INTERRUPT_SUCCESS: u16 = 0x06F5;
+
+//Both tests keep the number of the test they're on here
+const TEST_CASE: u16 = 0x0200;
+
+//The interrupt test pulls the cpu's interrupt lines by writing to this port,
+//bit 0 for IRQ and bit 1 for NMI
+const INTERRUPT_PORT: u16 = 0xBFFC;
+
+//Both tests finish well before this, it only stops a cpu that's lost
+const MAX_INSTRUCTIONS: u64 = 200_000_000;
+
+//A flat bus with the interrupt test's feedback port on it
+struct InterruptBus {
+    ram: FlatBus,
+}
+
+impl Bus for InterruptBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.ram.read(addr)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        self.ram.write(addr, val);
+    }
+
+    fn peek(&self, addr: u16) -> Option<u8> {
+        self.ram.peek(addr)
+    }
+
+    fn nmi_line(&self) -> bool {
+        (self.ram.ram[INTERRUPT_PORT as usize]&0x2) > 0
+    }
+
+    fn irq_line(&self) -> bool {
+        (self.ram.ram[INTERRUPT_PORT as usize]&0x1) > 0
+    }
+}
+
+//Runs 6502_functional_test.bin and 6502_interrupt_test.bin from a directory.
+//Returns true if both were found and passed
+pub fn run(dir: &str, functional_success: u16, interrupt_success: u16) -> bool {
+    let dir = Path::new(dir);
+
+    let functional = match load(&dir.join("6502_functional_test.bin")) {
+        Some(ram) => run_test("functional test", &mut FlatBus { ram: ram, log: None }, functional_success),
+        None => false,
+    };
+
+    let interrupt = match load(&dir.join("6502_interrupt_test.bin")) {
+        Some(ram) => {
+            let mut bus = InterruptBus { ram: FlatBus { ram: ram, log: None } };
+            bus.ram.ram[INTERRUPT_PORT as usize] = 0;
+
+            run_test("interrupt test", &mut bus, interrupt_success)
+        }
+        None => false,
+    };
+
+    functional && interrupt
+}
+
+//Reads a binary image of the whole 64K address space
+fn load(path: &Path) -> Option<Vec<u8>> {
+    let mut ram = Vec::new();
+
+    if let Err(why) = File::open(path).and_then(|mut file| file.read_to_end(&mut ram)) {
+        println!("couldn't read {}: {}", path.display(), why);
+        return None
+    }
+
+    if ram.len() != 0x10000 {
+        println!("{} should be a 64K image, but is {} bytes", path.display(), ram.len());
+        return None
+    }
+
+    Some(ram)
+}
+
+//Runs from the start address until the cpu traps itself in a loop (a jump or branch to itself).
+//Passing if that happened at the success address
+fn run_test<B: Bus>(name: &str, bus: &mut B, success: u16) -> bool {
+    let mut cpu = NESCpu::new();
+    cpu.set_variant(CpuVariant::Nmos6502);
+    cpu.set_unofficial_opcodes(false);
+    cpu.set_s(0xFF);
+    cpu.set_pc(START);
+
+    let mut instructions = 0u64;
+
+    let trap = loop {
+        let pc = cpu.pc();
+
+        if let Err(err) = cpu.do_instruction(bus) {
+            println!("{}: stopped, {}", name, err);
+            break None;
+        }
+
+        instructions += 1;
+
+        if cpu.pc() == pc {
+            break Some(pc);
+        }
+
+        if instructions == MAX_INSTRUCTIONS {
+            println!("{}: gave up after {} instructions", name, instructions);
+            break None;
+        }
+    };
+
+    if trap == Some(success) {
+        println!("{}: passed ({} instructions, {} cycles)", name, instructions, cpu.cycles());
+        return true
+    }
+
+    if let Some(pc) = trap {
+        println!("{}: failed, trapped at ${:04X} instead of ${:04X}", name, pc, success);
+    }
+
+    println!("{}: in test 0x{:02X} with A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PC:{:04X}",
+             name, bus.peek(TEST_CASE).unwrap_or(0), cpu.a(), cpu.x(), cpu.y(),
+             cpu.p().to_u8(), cpu.s(), cpu.pc());
+
+    false
+}
+
+//The binaries aren't in the repo, copy the prebuilt ones into tests/fixtures
+//and run these with cargo test -- --ignored
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures").join(name);
+
+        load(&path).expect("missing test binary, see the comment above")
+    }
+
+    #[test]
+    #[ignore]
+    fn functional_test() {
+        let mut bus = FlatBus { ram: fixture("6502_functional_test.bin"), log: None };
+
+        assert!(run_test("functional test", &mut bus, FUNCTIONAL_SUCCESS));
+    }
+
+    #[test]
+    #[ignore]
+    fn interrupt_test() {
+        let mut bus = InterruptBus { ram: FlatBus { ram: fixture("6502_interrupt_test.bin"), log: None } };
+        bus.ram.ram[INTERRUPT_PORT as usize] = 0;
+
+        assert!(run_test("interrupt test", &mut bus, INTERRUPT_SUCCESS));
+    }
+}
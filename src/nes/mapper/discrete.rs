@@ -0,0 +1,300 @@
+use super::{Mapper, Chr, prg_ram};
+use super::super::cart::{Rom, Mirroring};
+
+//The boards built from a latch or two instead of a mapper chip
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Board {
+    Uxrom, //002, 16KB PRG bank at $8000, the last bank fixed at $C000
+    Cnrom, //003, 8KB CHR bank
+    Axrom, //007, 32KB PRG bank, bit 4 picks the single screen
+    Bnrom, //034, 32KB PRG bank
+    Nina001, //034, 32KB PRG bank and two 4KB CHR banks, written at $7FFD-$7FFF
+    ColorDreams, //011, 32KB PRG bank in bits 0-1, 8KB CHR bank in bits 4-7
+    Gxrom, //066, 32KB PRG bank in bits 4-5, 8KB CHR bank in bits 0-1
+    Camerica, //071, 16KB PRG bank written at $C000-$FFFF, like UxROM
+    FireHawk, //071, Camerica with single screen mirroring picked at $8000-$9FFF
+}
+
+//Discrete logic mappers. Most boards latch whatever is written to $8000-$FFFF,
+//and the ones that don't stop the rom from driving the bus at the same time
+//get the value ANDed with the rom byte at that address (a bus conflict)
+//https://wiki.nesdev.com/w/index.php/Bus_conflict
+pub struct Discrete {
+    board: Board,
+    bus_conflicts: bool,
+
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+
+    mirroring: Mirroring,
+
+    prg_bank: usize,
+    chr_bank: usize,
+    chr_bank_hi: usize, //the NINA-001's bank at $1000
+}
+
+impl Discrete {
+    pub fn new(rom: Rom) -> Discrete {
+        let header = rom.header;
+
+        //Submappers tell the two mapper 034 and 071 boards apart
+        //https://wiki.nesdev.com/w/index.php/NES_2.0_submappers
+        let board = match (header.mapper, header.submapper) {
+            (2, _) => Board::Uxrom,
+            (3, _) => Board::Cnrom,
+            (7, _) => Board::Axrom,
+            (11, _) => Board::ColorDreams,
+            (34, 1) => Board::Nina001,
+            (34, 2) => Board::Bnrom,
+            (34, _) if header.chr_rom_size > 0x2000 => Board::Nina001,
+            (34, _) => Board::Bnrom,
+            (66, _) => Board::Gxrom,
+            (71, 1) => Board::FireHawk,
+            _ => Board::Camerica,
+        };
+
+        //UxROM, CNROM and AxROM come both ways, NES 2.0 submapper 1 marks the boards without
+        //bus conflicts and 2 the ones with them. With no submapper they're left off, since
+        //games for either board work without them. The other boards are always one or the other
+        let bus_conflicts = match (board, header.submapper) {
+            (Board::Uxrom, 2) | (Board::Cnrom, 2) | (Board::Axrom, 2) => true,
+            (Board::Uxrom, _) | (Board::Cnrom, _) | (Board::Axrom, _) => false,
+            (Board::Bnrom, _) | (Board::ColorDreams, _) | (Board::Gxrom, _) => true,
+            (Board::Nina001, _) | (Board::Camerica, _) | (Board::FireHawk, _) => false,
+        };
+
+        let mirroring = match board {
+            Board::Axrom | Board::FireHawk => Mirroring::SingleScreenLower,
+            _ if header.four_screen => Mirroring::FourScreen,
+            _ => header.mirroring,
+        };
+
+        Discrete {
+            board: board,
+            bus_conflicts: bus_conflicts,
+
+            prg_ram: prg_ram(&header, rom.trainer),
+            chr: Chr::new(rom.chr_rom, &header),
+            prg_rom: rom.prg_rom,
+
+            mirroring: mirroring,
+
+            prg_bank: 0,
+            chr_bank: 0,
+            chr_bank_hi: 1,
+        }
+    }
+
+    fn prg_rom_addr(&self, addr: u16) -> usize {
+        let addr = addr as usize;
+
+        let addr = match self.board {
+            Board::Uxrom | Board::Camerica | Board::FireHawk => {
+                //A rom smaller than a bank is mirrored through it
+                let last = (self.prg_rom.len() / 0x4000).max(1) - 1;
+                let bank = if addr < 0xC000 { self.prg_bank } else { last };
+
+                bank * 0x4000 + (addr & 0x3FFF)
+            }
+
+            //Like NROM, a 16KB rom shows up twice
+            Board::Cnrom => addr - 0x8000,
+
+            _ => self.prg_bank * 0x8000 + (addr & 0x7FFF),
+        };
+
+        addr % self.prg_rom.len()
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let addr = addr as usize;
+
+        match self.board {
+            Board::Nina001 => {
+                let bank = if addr < 0x1000 { self.chr_bank } else { self.chr_bank_hi };
+                bank * 0x1000 + (addr & 0x0FFF)
+            }
+
+            _ => self.chr_bank * 0x2000 + addr,
+        }
+    }
+
+    fn write_latch(&mut self, addr: u16, val: u8) {
+        let val = val as usize;
+
+        match self.board {
+            Board::Uxrom => self.prg_bank = val,
+            Board::Cnrom => self.chr_bank = val,
+
+            Board::Axrom => {
+                self.prg_bank = val & 0x7;
+                self.mirroring = if val & 0x10 > 0 { Mirroring::SingleScreenUpper } else { Mirroring::SingleScreenLower };
+            }
+
+            Board::Bnrom => self.prg_bank = val,
+
+            Board::ColorDreams => {
+                self.prg_bank = val & 0x3;
+                self.chr_bank = val >> 4;
+            }
+
+            Board::Gxrom => {
+                self.prg_bank = (val >> 4) & 0x3;
+                self.chr_bank = val & 0x3;
+            }
+
+            //The mirroring register only exists on Fire Hawk's board,
+            //other Camerica games write to $8000 for an older chip
+            Board::Camerica | Board::FireHawk => match addr {
+                0x8000...0x9FFF if self.board == Board::FireHawk => {
+                    self.mirroring = if val & 0x10 > 0 { Mirroring::SingleScreenUpper } else { Mirroring::SingleScreenLower };
+                }
+
+                0xC000...0xFFFF => self.prg_bank = val,
+
+                _ => {}
+            },
+
+            Board::Nina001 => {}
+        }
+    }
+}
+
+impl Mapper for Discrete {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.cpu_peek(addr).unwrap_or(0)
+    }
+
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x6000...0x7FFF => {
+                let len = self.prg_ram.len();
+                if len > 0 {
+                    self.prg_ram[(addr - 0x6000) as usize % len] = val;
+                }
+
+                //The NINA-001's registers sit on top of its RAM (if the header gives
+                //it any), which gets written too
+                if self.board == Board::Nina001 {
+                    match addr {
+                        0x7FFD => self.prg_bank = (val & 0x1) as usize,
+                        0x7FFE => self.chr_bank = (val & 0xF) as usize,
+                        0x7FFF => self.chr_bank_hi = (val & 0xF) as usize,
+                        _ => {}
+                    }
+                }
+            }
+
+            0x8000...0xFFFF if self.prg_rom.len() > 0 => {
+                let val = if self.bus_conflicts {
+                    val & self.prg_rom[self.prg_rom_addr(addr)]
+                } else {
+                    val
+                };
+
+                self.write_latch(addr, val);
+            }
+
+            _ => {}
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000...0x7FFF if self.prg_ram.len() > 0 => {
+                Some(self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()])
+            }
+
+            0x8000...0xFFFF if self.prg_rom.len() > 0 => {
+                Some(self.prg_rom[self.prg_rom_addr(addr)])
+            }
+
+            _ => None,
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_addr(addr))
+    }
+
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        let addr = self.chr_addr(addr);
+        self.chr.write(addr, val);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_rom::{TestRom, numbered_banks};
+
+    //64KB of PRG ROM in numbered 32KB banks, 16KB of CHR ROM and no PRG RAM
+    fn mapper(number: u16, submapper: u8) -> Discrete {
+        Discrete::new(TestRom::new(number)
+            .submapper(submapper)
+            .prg_rom(numbered_banks(2, 0x8000))
+            .chr_rom(vec![0u8; 0x4000])
+            .build())
+    }
+
+    #[test]
+    fn bus_conflicts_follow_the_board() {
+        let boards = [
+            (2, 0, Board::Uxrom, false),
+            (2, 1, Board::Uxrom, false),
+            (2, 2, Board::Uxrom, true),
+            (7, 2, Board::Axrom, true),
+            (34, 1, Board::Nina001, false),
+            (34, 2, Board::Bnrom, true),
+            (66, 0, Board::Gxrom, true),
+            (71, 0, Board::Camerica, false),
+        ];
+
+        for &(number, submapper, board, bus_conflicts) in &boards {
+            let mapper = mapper(number, submapper);
+
+            assert_eq!((mapper.board, mapper.bus_conflicts), (board, bus_conflicts), "{}.{}", number, submapper);
+        }
+    }
+
+    //Bank 0 is all zeroes, so the write gets ANDed down to 0
+    #[test]
+    fn bnrom_writes_are_anded_with_the_rom() {
+        let mut mapper = mapper(34, 2);
+        mapper.cpu_write(0x8000, 1);
+
+        assert_eq!(mapper.prg_bank, 0);
+    }
+
+    #[test]
+    fn nina001_registers_work_without_prg_ram() {
+        let mut mapper = mapper(34, 1);
+        assert_eq!(mapper.prg_ram.len(), 0);
+
+        mapper.cpu_write(0x7FFD, 1);
+        mapper.cpu_write(0x7FFF, 3);
+
+        assert_eq!(mapper.cpu_read(0x8000), 1);
+        assert_eq!(mapper.chr_bank_hi, 3);
+    }
+
+    //8KB of PRG ROM is less than one bank on every board, it shows up everywhere
+    #[test]
+    fn prg_smaller_than_a_bank_is_mirrored() {
+        for &(number, submapper) in &[(2, 0), (3, 0), (7, 0), (11, 0), (34, 1), (34, 2), (66, 0), (71, 0), (71, 1)] {
+            let mut mapper = Discrete::new(TestRom::new(number)
+                .submapper(submapper)
+                .prg_rom(numbered_banks(4, 0x800))
+                .build());
+
+            for &addr in &[0x8000, 0xBFFF, 0xC000, 0xFFFF] {
+                assert_eq!(mapper.cpu_read(addr), ((addr & 0x1FFF) / 0x800) as u8, "{}.{} at {:04X}", number, submapper, addr);
+            }
+        }
+    }
+}
@@ -0,0 +1,396 @@
+use super::{Mapper, Chr, prg_ram};
+use super::super::cart::{Rom, Mirroring};
+
+//The boards that use the MMC1's spare CHR bank bits for something else
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Board {
+    Standard,
+    Snrom, //CHR bit 4 disables PRG RAM
+    Sorom, //CHR bit 3 picks one of two 8KB PRG RAM banks
+    Surom, //CHR bit 4 picks which 256KB half of 512KB PRG ROM
+    Sxrom, //both of the above, with CHR bits 2-3 picking one of four PRG RAM banks
+    Serom, //32KB of PRG ROM, which can't be switched
+}
+
+//Mapper 001, the MMC1. Registers are written one bit at a time through a
+//serial port at $8000-$FFFF, and the address of the fifth write picks the register
+//https://wiki.nesdev.com/w/index.php/MMC1
+pub struct Mmc1 {
+    board: Board,
+
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+
+    shift: u8, //the 1 starts at bit 4 and falls out of bit 0 on the fifth write
+    control: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    prg_bank: u8,
+
+    //Writes on the cycle right after another are ignored, which
+    //matters for read-modify-write instructions writing twice. When
+    //the cpu runs whole instructions the clock only catches up after
+    //them, so both of those writes land on the same cycle instead
+    cycle: u64,
+    last_write: Option<u64>,
+}
+
+impl Mmc1 {
+    pub fn new(rom: Rom) -> Mmc1 {
+        let header = rom.header;
+        let prg_ram_size = header.prg_ram_size + header.prg_nvram_size;
+
+        //NES 2.0 submappers 1, 2 and 4 name the board, older roms are told apart by size
+        //https://wiki.nesdev.com/w/index.php/NES_2.0_submappers#001:_MMC1
+        let board = match header.submapper {
+            1 => Board::Surom,
+            2 => Board::Sorom,
+            4 => Board::Sxrom,
+            5 => Board::Serom,
+            _ if header.prg_rom_size > 0x40000 && prg_ram_size >= 0x8000 => Board::Sxrom,
+            _ if header.prg_rom_size > 0x40000 => Board::Surom,
+            _ if prg_ram_size == 0x4000 => Board::Sorom,
+            _ if header.chr_rom_size == 0 => Board::Snrom,
+            _ => Board::Standard,
+        };
+
+        Mmc1 {
+            board: board,
+
+            prg_ram: prg_ram(&header, rom.trainer),
+            chr: Chr::new(rom.chr_rom, &header),
+            prg_rom: rom.prg_rom,
+
+            shift: 0x10,
+            control: 0x0C, //PRG mode 3 at power on, so the reset vector is in the fixed last bank
+            chr_bank0: 0,
+            chr_bank1: 0,
+            prg_bank: 0,
+
+            cycle: 0,
+            last_write: None,
+        }
+    }
+
+    //The 256KB half of PRG ROM on SUROM and SXROM
+    fn prg_outer_bank(&self) -> usize {
+        match self.board {
+            Board::Surom | Board::Sxrom => (self.chr_bank0 & 0x10) as usize,
+            _ => 0,
+        }
+    }
+
+    fn prg_rom_addr(&self, addr: u16) -> usize {
+        let outer = self.prg_outer_bank();
+        let bank = (self.prg_bank & 0x0F) as usize;
+
+        //16KB bank at $8000 and at $C000
+        let (lo, hi) = match (self.board, (self.control >> 2) & 0x3) {
+            (Board::Serom, _) => (0, 1),
+
+            //32KB at a time, ignoring the low bit of the bank
+            (_, 0) | (_, 1) => (bank & !1, bank | 1),
+
+            //First bank fixed at $8000
+            (_, 2) => (0, bank),
+
+            //Last bank fixed at $C000
+            _ => (bank, 0x0F),
+        };
+
+        let bank = if addr < 0xC000 { lo } else { hi };
+
+        ((outer | bank) * 0x4000 + (addr as usize & 0x3FFF)) % self.prg_rom.len()
+    }
+
+    //None when PRG RAM is missing or turned off
+    fn prg_ram_addr(&self, addr: u16) -> Option<usize> {
+        if self.prg_ram.len() == 0 || self.prg_bank & 0x10 > 0 {
+            return None
+        }
+
+        let bank = match self.board {
+            Board::Snrom if self.chr_bank0 & 0x10 > 0 => return None,
+            Board::Sorom => ((self.chr_bank0 >> 3) & 0x1) as usize,
+            Board::Sxrom => ((self.chr_bank0 >> 2) & 0x3) as usize,
+            _ => 0,
+        };
+
+        Some((bank * 0x2000 + (addr - 0x6000) as usize) % self.prg_ram.len())
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        //8KB at a time, ignoring the low bit of the bank
+        if self.control & 0x10 == 0 {
+            return (self.chr_bank0 & 0x1E) as usize * 0x1000 + addr as usize
+        }
+
+        let bank = if addr < 0x1000 { self.chr_bank0 } else { self.chr_bank1 };
+
+        bank as usize * 0x1000 + (addr as usize & 0x0FFF)
+    }
+
+    fn write_register(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x8000...0x9FFF => self.control = val,
+            0xA000...0xBFFF => self.chr_bank0 = val,
+            0xC000...0xDFFF => self.chr_bank1 = val,
+            _ => self.prg_bank = val,
+        }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.cpu_peek(addr).unwrap_or(0)
+    }
+
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x6000...0x7FFF => {
+                if let Some(addr) = self.prg_ram_addr(addr) {
+                    self.prg_ram[addr] = val;
+                }
+            }
+
+            0x8000...0xFFFF => {
+                let consecutive = self.last_write == Some(self.cycle)
+                    || self.last_write == Some(self.cycle.wrapping_sub(1));
+                self.last_write = Some(self.cycle);
+
+                if consecutive {
+                    return;
+                }
+
+                //Bit 7 resets the shift register and goes back to PRG mode 3
+                if val & 0x80 > 0 {
+                    self.shift = 0x10;
+                    self.control |= 0x0C;
+                    return;
+                }
+
+                let full = self.shift & 0x1 > 0;
+                self.shift = (self.shift >> 1) | ((val & 0x1) << 4);
+
+                if full {
+                    let val = self.shift;
+                    self.write_register(addr, val);
+                    self.shift = 0x10;
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000...0x7FFF => {
+                self.prg_ram_addr(addr).map(|addr| self.prg_ram[addr])
+            }
+
+            0x8000...0xFFFF if self.prg_rom.len() > 0 => {
+                Some(self.prg_rom[self.prg_rom_addr(addr)])
+            }
+
+            _ => None,
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_addr(addr))
+    }
+
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        let addr = self.chr_addr(addr);
+        self.chr.write(addr, val);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x3 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn cpu_clock(&mut self) {
+        self.cycle = self.cycle.wrapping_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_rom::{TestRom, numbered_banks};
+
+    //256KB of PRG in numbered 16KB banks, 128KB of CHR in numbered 4KB banks
+    fn standard() -> Mmc1 {
+        Mmc1::new(TestRom::new(1)
+            .prg_rom(numbered_banks(16, 0x4000))
+            .chr_rom(numbered_banks(32, 0x1000))
+            .prg_ram(0x2000)
+            .build())
+    }
+
+    //512KB of PRG in numbered 16KB banks
+    fn large(rom: TestRom) -> Mmc1 {
+        Mmc1::new(rom.prg_rom(numbered_banks(32, 0x4000)).chr_ram(0x2000).build())
+    }
+
+    //The five serial writes that load a register, far enough apart to all count
+    fn write(mapper: &mut Mmc1, addr: u16, val: u8) {
+        for bit in 0..5 {
+            mapper.cpu_write(addr, (val >> bit) & 0x1);
+            mapper.cpu_clock();
+            mapper.cpu_clock();
+        }
+    }
+
+    #[test]
+    fn prg_mode_3_fixes_the_last_bank_at_c000() {
+        let mut mapper = standard();
+        write(&mut mapper, 0xE000, 5);
+
+        assert_eq!((mapper.cpu_read(0x8000), mapper.cpu_read(0xBFFF)), (5, 5));
+        assert_eq!((mapper.cpu_read(0xC000), mapper.cpu_read(0xFFFF)), (15, 15));
+    }
+
+    #[test]
+    fn prg_mode_2_fixes_the_first_bank_at_8000() {
+        let mut mapper = standard();
+        write(&mut mapper, 0x8000, 0x08);
+        write(&mut mapper, 0xE000, 5);
+
+        assert_eq!((mapper.cpu_read(0x8000), mapper.cpu_read(0xC000)), (0, 5));
+    }
+
+    #[test]
+    fn prg_modes_0_and_1_switch_32kb_ignoring_the_low_bit() {
+        for &control in &[0x00, 0x04] {
+            let mut mapper = standard();
+            write(&mut mapper, 0x8000, control);
+            write(&mut mapper, 0xE000, 5);
+
+            assert_eq!((mapper.cpu_read(0x8000), mapper.cpu_read(0xC000)), (4, 5), "control {:02X}", control);
+        }
+    }
+
+    #[test]
+    fn chr_8kb_mode_ignores_the_low_bit() {
+        let mut mapper = standard();
+        write(&mut mapper, 0x8000, 0x0C);
+        write(&mut mapper, 0xA000, 5);
+        write(&mut mapper, 0xC000, 9);
+
+        assert_eq!((mapper.ppu_read(0x0000), mapper.ppu_read(0x1000)), (4, 5));
+    }
+
+    #[test]
+    fn chr_4kb_mode_switches_each_half() {
+        let mut mapper = standard();
+        write(&mut mapper, 0x8000, 0x1C);
+        write(&mut mapper, 0xA000, 5);
+        write(&mut mapper, 0xC000, 9);
+
+        assert_eq!((mapper.ppu_read(0x0000), mapper.ppu_read(0x1FFF)), (5, 9));
+    }
+
+    #[test]
+    fn prg_ram_can_be_turned_off() {
+        let mut mapper = standard();
+        mapper.cpu_write(0x6000, 0xAA);
+        assert_eq!(mapper.cpu_read(0x6000), 0xAA);
+
+        write(&mut mapper, 0xE000, 0x10);
+        mapper.cpu_write(0x6000, 0xBB);
+        assert_eq!(mapper.cpu_peek(0x6000), None);
+
+        write(&mut mapper, 0xE000, 0x00);
+        assert_eq!(mapper.cpu_read(0x6000), 0xAA);
+    }
+
+    //CHR bank 0's bit 4 picks the 256KB half, for the fixed bank too
+    #[test]
+    fn surom_switches_256kb_halves() {
+        for rom in vec![TestRom::new(1).submapper(1), TestRom::new(1).prg_ram(0x2000)] {
+            let mut mapper = large(rom);
+            assert_eq!(mapper.board, Board::Surom);
+
+            write(&mut mapper, 0xE000, 2);
+            assert_eq!((mapper.cpu_read(0x8000), mapper.cpu_read(0xC000)), (2, 15));
+
+            write(&mut mapper, 0xA000, 0x10);
+            assert_eq!((mapper.cpu_read(0x8000), mapper.cpu_read(0xC000)), (18, 31));
+        }
+    }
+
+    //CHR bank 0's bit 3 picks one of the two 8KB PRG RAM banks
+    #[test]
+    fn sorom_switches_prg_ram_banks() {
+        let mut mapper = Mmc1::new(TestRom::new(1).prg_ram(0x2000).prg_nvram(0x2000).chr_ram(0x2000).build());
+        assert_eq!(mapper.board, Board::Sorom);
+
+        mapper.cpu_write(0x6000, 0xAA);
+        write(&mut mapper, 0xA000, 0x08);
+        assert_eq!(mapper.cpu_read(0x6000), 0x00);
+
+        mapper.cpu_write(0x6000, 0xBB);
+        write(&mut mapper, 0xA000, 0x00);
+        assert_eq!(mapper.cpu_read(0x6000), 0xAA);
+    }
+
+    //CHR bank 0's bits 2-3 pick one of four 8KB PRG RAM banks, and bit 4 the PRG half
+    #[test]
+    fn sxrom_switches_prg_ram_banks_and_256kb_halves() {
+        let mut mapper = large(TestRom::new(1).prg_nvram(0x8000));
+        assert_eq!(mapper.board, Board::Sxrom);
+
+        for bank in 0..4 {
+            write(&mut mapper, 0xA000, bank << 2);
+            mapper.cpu_write(0x6000, bank + 1);
+        }
+        for bank in 0..4 {
+            write(&mut mapper, 0xA000, bank << 2);
+            assert_eq!(mapper.cpu_read(0x6000), bank + 1);
+        }
+
+        write(&mut mapper, 0xA000, 0x10);
+        assert_eq!(mapper.cpu_read(0xC000), 31);
+    }
+
+    //Loading PRG bank 5 (bits 1, 0, 1, 0, 0) with an extra 0 written right after the first
+    //1, which would load bank 9 instead if it wasn't ignored
+    fn write_with_extra(mapper: &mut Mmc1, clock_extra: bool) {
+        mapper.cpu_write(0xE000, 1);
+        if clock_extra {
+            mapper.cpu_clock();
+        }
+        mapper.cpu_write(0xE000, 0);
+
+        for &bit in &[0, 1, 0, 0] {
+            mapper.cpu_clock();
+            mapper.cpu_clock();
+            mapper.cpu_write(0xE000, bit);
+        }
+    }
+
+    #[test]
+    fn writes_on_the_next_cycle_are_ignored() {
+        let mut mapper = standard();
+        write_with_extra(&mut mapper, true);
+
+        assert_eq!(mapper.cpu_read(0x8000), 5);
+    }
+
+    //A read-modify-write run as a whole instruction, before the clock catches up
+    #[test]
+    fn writes_on_the_same_cycle_are_ignored() {
+        let mut mapper = standard();
+        write_with_extra(&mut mapper, false);
+
+        assert_eq!(mapper.cpu_read(0x8000), 5);
+    }
+}
@@ -0,0 +1,250 @@
+use super::{Mapper, Chr, prg_ram};
+use super::super::cart::{Rom, Mirroring};
+
+//Mapper 009 (the MMC2) and 010 (the MMC4). Each half of the pattern tables has two
+//4KB CHR banks and a latch picking between them, which flips when the ppu fetches
+//the pattern of tile $FD or $FE from that half. The MMC2 has an 8KB PRG bank, the
+//MMC4 a 16KB one and PRG RAM
+//https://wiki.nesdev.com/w/index.php/MMC2
+//https://wiki.nesdev.com/w/index.php/MMC4
+pub struct Mmc2 {
+    mmc4: bool,
+
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+
+    prg_bank: u8,
+    chr_banks: [[u8; 2]; 2], //for each half, the bank used while its latch is $FD and $FE
+    latches: [usize; 2], //0 for $FD, 1 for $FE
+    mirroring: u8,
+}
+
+impl Mmc2 {
+    pub fn new(rom: Rom) -> Mmc2 {
+        let mmc4 = rom.header.mapper == 10;
+
+        Mmc2 {
+            mmc4: mmc4,
+
+            prg_ram: if mmc4 { prg_ram(&rom.header, rom.trainer) } else { Vec::new() },
+            chr: Chr::new(rom.chr_rom, &rom.header),
+            prg_rom: rom.prg_rom,
+
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [1, 1],
+            mirroring: 0,
+        }
+    }
+
+    //The switchable bank at $8000, then the last banks fixed after it. They're
+    //counted back from the end, wrapping around on roms smaller than $8000-$FFFF
+    fn prg_rom_addr(&self, addr: u16) -> usize {
+        let addr = addr as usize;
+        let bank_size = if self.mmc4 { 0x4000 } else { 0x2000 };
+        let banks = (self.prg_rom.len() / bank_size).max(1);
+        let window = 0x8000 / bank_size;
+
+        let bank = match (addr - 0x8000) / bank_size {
+            0 => self.prg_bank as usize,
+            n => (banks * window + n - window) % banks,
+        };
+
+        (bank * bank_size + (addr & (bank_size - 1))) % self.prg_rom.len()
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let half = (addr >> 12) as usize & 0x1;
+        let bank = self.chr_banks[half][self.latches[half]];
+
+        bank as usize * 0x1000 + (addr as usize & 0x0FFF)
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.cpu_peek(addr).unwrap_or(0)
+    }
+
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x6000...0x7FFF if self.prg_ram.len() > 0 => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = val;
+            }
+
+            0xA000...0xAFFF => self.prg_bank = val & 0x0F,
+            0xB000...0xBFFF => self.chr_banks[0][0] = val & 0x1F,
+            0xC000...0xCFFF => self.chr_banks[0][1] = val & 0x1F,
+            0xD000...0xDFFF => self.chr_banks[1][0] = val & 0x1F,
+            0xE000...0xEFFF => self.chr_banks[1][1] = val & 0x1F,
+            0xF000...0xFFFF => self.mirroring = val & 0x1,
+
+            _ => {}
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000...0x7FFF if self.prg_ram.len() > 0 => {
+                Some(self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()])
+            }
+
+            0x8000...0xFFFF if self.prg_rom.len() > 0 => {
+                Some(self.prg_rom[self.prg_rom_addr(addr)])
+            }
+
+            _ => None,
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_addr(addr))
+    }
+
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        let addr = self.chr_addr(addr);
+        self.chr.write(addr, val);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.mirroring == 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        }
+    }
+
+    //The latch flips after the fetch, so tile $FD or $FE itself
+    //is still drawn from the bank picked before it
+    fn ppu_address(&mut self, addr: u16) {
+        let half = (addr >> 12) as usize & 0x1;
+
+        let latch = match (addr & 0x0FF8, addr & 0x7) {
+            (0x0FD8, 0) => 0,
+            (0x0FE8, 0) => 1,
+
+            //The MMC2's right half and the whole MMC4 watch all eight rows of the tile
+            (0x0FD8, _) if self.mmc4 || half == 1 => 0,
+            (0x0FE8, _) if self.mmc4 || half == 1 => 1,
+
+            _ => return,
+        };
+
+        self.latches[half] = latch;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_rom::{TestRom, numbered_banks};
+
+    //32KB of CHR in numbered 4KB banks, with banks 0-3 picked
+    //for left $FD, left $FE, right $FD and right $FE
+    fn mapper(number: u16) -> Mmc2 {
+        let mut mapper = Mmc2::new(TestRom::new(number).chr_rom(numbered_banks(8, 0x1000)).build());
+        mapper.cpu_write(0xB000, 0);
+        mapper.cpu_write(0xC000, 1);
+        mapper.cpu_write(0xD000, 2);
+        mapper.cpu_write(0xE000, 3);
+
+        mapper
+    }
+
+    //What the ppu does for a pattern fetch
+    fn fetch(mapper: &mut Mmc2, addr: u16) -> u8 {
+        let val = mapper.ppu_read(addr);
+        mapper.ppu_address(addr);
+
+        val
+    }
+
+    //The bank a half is drawing from
+    fn bank(mapper: &mut Mmc2, half: u16) -> u8 {
+        fetch(mapper, half)
+    }
+
+    #[test]
+    fn left_latch_flips_on_the_first_row_only() {
+        let mut mapper = mapper(9);
+        assert_eq!(bank(&mut mapper, 0x0000), 1);
+
+        //The trigger fetch itself still comes from the old bank
+        assert_eq!(fetch(&mut mapper, 0x0FD8), 1);
+        assert_eq!(bank(&mut mapper, 0x0000), 0);
+
+        assert_eq!(fetch(&mut mapper, 0x0FE8), 0);
+        assert_eq!(bank(&mut mapper, 0x0000), 1);
+
+        for addr in 0x0FD9..0x0FE0 {
+            fetch(&mut mapper, addr);
+            assert_eq!(bank(&mut mapper, 0x0000), 1, "{:04X}", addr);
+        }
+
+        fetch(&mut mapper, 0x0FD8);
+        for addr in 0x0FE9..0x0FF0 {
+            fetch(&mut mapper, addr);
+            assert_eq!(bank(&mut mapper, 0x0000), 0, "{:04X}", addr);
+        }
+    }
+
+    #[test]
+    fn right_latch_flips_on_every_row() {
+        for &number in &[9, 10] {
+            let mut mapper = mapper(number);
+
+            for row in 0..8 {
+                let fd = 0x1FD8 + row;
+                let fe = 0x1FE8 + row;
+
+                assert_eq!(fetch(&mut mapper, fd), 3, "{:04X}", fd);
+                assert_eq!(bank(&mut mapper, 0x1000), 2, "{:04X}", fd);
+
+                assert_eq!(fetch(&mut mapper, fe), 2, "{:04X}", fe);
+                assert_eq!(bank(&mut mapper, 0x1000), 3, "{:04X}", fe);
+
+                //The other half doesn't move
+                assert_eq!(bank(&mut mapper, 0x0000), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn mmc4_left_latch_flips_on_every_row() {
+        let mut mapper = mapper(10);
+
+        for row in 0..8 {
+            assert_eq!(fetch(&mut mapper, 0x0FD8 + row), 1);
+            assert_eq!(bank(&mut mapper, 0x0000), 0, "{:04X}", 0x0FD8 + row);
+
+            assert_eq!(fetch(&mut mapper, 0x0FE8 + row), 0);
+            assert_eq!(bank(&mut mapper, 0x0000), 1, "{:04X}", 0x0FE8 + row);
+        }
+    }
+
+    //The last three 8KB banks (or last 16KB bank) are fixed
+    #[test]
+    fn fixed_prg_banks_are_the_last_ones() {
+        let mut mmc2 = Mmc2::new(TestRom::new(9).prg_rom(numbered_banks(16, 0x2000)).build());
+        mmc2.cpu_write(0xA000, 5);
+        let banks: Vec<u8> = [0x8000, 0xA000, 0xC000, 0xE000].iter().map(|&addr| mmc2.cpu_read(addr)).collect();
+        assert_eq!(banks, vec![5, 13, 14, 15]);
+
+        let mut mmc4 = Mmc2::new(TestRom::new(10).prg_rom(numbered_banks(8, 0x4000)).build());
+        mmc4.cpu_write(0xA000, 5);
+        assert_eq!((mmc4.cpu_read(0x8000), mmc4.cpu_read(0xC000)), (5, 7));
+    }
+
+    //Smaller than the PRG window, so the fixed banks wrap around instead of underflowing
+    #[test]
+    fn small_prg_roms_wrap_around() {
+        for &(number, size) in &[(9, 0x2000), (9, 0x4000), (10, 0x2000), (10, 0x4000)] {
+            let mut mapper = Mmc2::new(TestRom::new(number).prg_rom(numbered_banks(size / 0x2000, 0x2000)).build());
+            let last = (size / 0x2000 - 1) as u8;
+
+            assert_eq!(mapper.cpu_read(0xFFFF), last, "mapper {} with {} bytes", number, size);
+        }
+    }
+}
@@ -0,0 +1,430 @@
+use super::{Mapper, Chr, prg_ram};
+use super::super::cart::{Rom, Mirroring};
+use super::super::irq::{IrqLine, IrqSource};
+
+//The chips that share mapper 004 but act a little differently
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Chip {
+    Mmc3,
+    Mmc3A, //the old IRQ behaviour, see clock_irq
+    Mmc6, //1KB of PRG RAM inside the chip, with its own protection bits
+}
+
+//Mapper 004, the MMC3 (and the MMC6). 8KB PRG banks, 1KB and 2KB CHR banks, and
+//a scanline counter clocked by the ppu's A12 going high, which it does once a
+//line when the background and sprites use different pattern tables
+//https://wiki.nesdev.com/w/index.php/MMC3
+pub struct Mmc3 {
+    chip: Chip,
+
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    four_screen: bool,
+
+    bank_select: u8,
+    banks: [u8; 8], //R0-R7, picked by the low bits of bank_select
+    mirroring: u8,
+    prg_ram_protect: u8,
+
+    irq: IrqLine,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+
+    //A12 has to stay low for a few cpu cycles before a rise counts,
+    //which filters out the quick dips between nearby fetches
+    cycle: u64,
+    a12: bool,
+    a12_low_since: u64,
+}
+
+impl Mmc3 {
+    pub fn new(rom: Rom, irq: IrqLine) -> Mmc3 {
+        let header = rom.header;
+
+        //https://wiki.nesdev.com/w/index.php/NES_2.0_submappers#004:_MMC3
+        let chip = match header.submapper {
+            1 => Chip::Mmc6,
+            4 => Chip::Mmc3A,
+            _ => Chip::Mmc3,
+        };
+
+        let prg_ram = match chip {
+            Chip::Mmc6 => vec![0u8; 0x400],
+            _ => prg_ram(&header, rom.trainer),
+        };
+
+        Mmc3 {
+            chip: chip,
+
+            prg_ram: prg_ram,
+            chr: Chr::new(rom.chr_rom, &header),
+            prg_rom: rom.prg_rom,
+            four_screen: header.four_screen,
+
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: 0,
+            prg_ram_protect: 0x80, //some games never turn PRG RAM on, so start with it enabled
+
+            irq: irq,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+
+            cycle: 0,
+            a12: false,
+            a12_low_since: 0,
+        }
+    }
+
+    fn prg_rom_addr(&self, addr: u16) -> usize {
+        //Roms with fewer than two banks wrap around
+        let banks = (self.prg_rom.len() / 0x2000).max(1);
+        let last = banks - 1;
+        let second_last = (banks + last - 1) % banks;
+
+        //Bit 6 of bank select swaps $8000 with the fixed second to last bank at $C000
+        let bank = match (addr, self.bank_select & 0x40 > 0) {
+            (0x8000...0x9FFF, false) | (0xC000...0xDFFF, true) => self.banks[6] as usize,
+            (0x8000...0x9FFF, true) | (0xC000...0xDFFF, false) => second_last,
+            (0xA000...0xBFFF, _) => self.banks[7] as usize,
+            _ => last,
+        };
+
+        (bank * 0x2000 + (addr as usize & 0x1FFF)) % self.prg_rom.len()
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        //Bit 7 of bank select swaps the 2KB banks with the 1KB ones
+        let addr = if self.bank_select & 0x80 > 0 { addr ^ 0x1000 } else { addr };
+
+        let bank = match addr {
+            0x0000...0x07FF => self.banks[0] & 0xFE | ((addr >> 10) & 0x1) as u8,
+            0x0800...0x0FFF => self.banks[1] & 0xFE | ((addr >> 10) & 0x1) as u8,
+            0x1000...0x13FF => self.banks[2],
+            0x1400...0x17FF => self.banks[3],
+            0x1800...0x1BFF => self.banks[4],
+            _ => self.banks[5],
+        };
+
+        bank as usize * 0x0400 + (addr as usize & 0x03FF)
+    }
+
+    //The MMC6's RAM is two 512 byte halves at $7000 (mirrored up to $7FFF), and
+    //$A001 has a read and a write enable for each. None when PRG RAM is missing or off
+    //https://wiki.nesdev.com/w/index.php/MMC6
+    fn prg_ram_addr(&self, addr: u16, write: bool) -> Option<usize> {
+        if self.prg_ram.len() == 0 {
+            return None
+        }
+
+        match self.chip {
+            Chip::Mmc6 => {
+                if self.bank_select & 0x20 == 0 || addr < 0x7000 {
+                    return None
+                }
+
+                let high = addr & 0x200 > 0;
+                let shift = if high { 6 } else { 4 };
+                let enabled = if write { 0x1 } else { 0x2 };
+
+                if (self.prg_ram_protect >> shift) & enabled > 0 {
+                    Some(addr as usize & 0x3FF)
+                } else {
+                    None
+                }
+            }
+
+            _ => {
+                //Bit 7 turns the chip on, bit 6 protects it from writes
+                if self.prg_ram_protect & 0x80 == 0 || (write && self.prg_ram_protect & 0x40 > 0) {
+                    return None
+                }
+
+                Some((addr - 0x6000) as usize % self.prg_ram.len())
+            }
+        }
+    }
+
+    //Runs on every rise of A12. The counter reloads when it's at 0 (or a reload was asked for)
+    //and counts down otherwise. Newer chips fire whenever it ends up at 0, the MMC3A only
+    //when it counts down to 0 or a reload was asked for, so a latch of 0 fires just once
+    //https://wiki.nesdev.com/w/index.php/MMC3#IRQ_Specifics
+    fn clock_irq(&mut self) {
+        let was_zero = self.irq_counter == 0;
+        let reload = self.irq_reload;
+
+        if was_zero || reload {
+            self.irq_counter = self.irq_latch;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        self.irq_reload = false;
+
+        let fire = match self.chip {
+            Chip::Mmc3A => self.irq_counter == 0 && (!was_zero || reload),
+            _ => self.irq_counter == 0,
+        };
+
+        if fire && self.irq_enabled {
+            self.irq.assert(IrqSource::Mapper);
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.cpu_peek(addr).unwrap_or(0)
+    }
+
+    //Each register is at every even or every odd address of an 8KB range
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match (addr, addr & 0x1 > 0) {
+            (0x6000...0x7FFF, _) => {
+                if let Some(addr) = self.prg_ram_addr(addr, true) {
+                    self.prg_ram[addr] = val;
+                }
+            }
+
+            (0x8000...0x9FFF, false) => self.bank_select = val,
+            (0x8000...0x9FFF, true) => self.banks[(self.bank_select & 0x7) as usize] = val,
+
+            (0xA000...0xBFFF, false) => self.mirroring = val & 0x1,
+
+            //The MMC6 ignores its protection bits while its RAM is off
+            (0xA000...0xBFFF, true) => {
+                if self.chip != Chip::Mmc6 || self.bank_select & 0x20 > 0 {
+                    self.prg_ram_protect = val;
+                }
+            }
+
+            (0xC000...0xDFFF, false) => self.irq_latch = val,
+            (0xC000...0xDFFF, true) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+
+            //Disabling also acknowledges an IRQ that's already pending
+            (0xE000...0xFFFF, false) => {
+                self.irq_enabled = false;
+                self.irq.release(IrqSource::Mapper);
+            }
+            (0xE000...0xFFFF, true) => self.irq_enabled = true,
+
+            _ => {}
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000...0x7FFF => {
+                match self.prg_ram_addr(addr, false) {
+                    Some(addr) => Some(self.prg_ram[addr]),
+
+                    //With only the other half of the MMC6's RAM readable this half reads 0
+                    None if self.chip == Chip::Mmc6 && addr >= 0x7000 && self.bank_select & 0x20 > 0
+                        && self.prg_ram_protect & 0xA0 > 0 => Some(0),
+
+                    None => None,
+                }
+            }
+
+            0x8000...0xFFFF if self.prg_rom.len() > 0 => {
+                Some(self.prg_rom[self.prg_rom_addr(addr)])
+            }
+
+            _ => None,
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_addr(addr))
+    }
+
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        let addr = self.chr_addr(addr);
+        self.chr.write(addr, val);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.four_screen {
+            Mirroring::FourScreen
+        } else if self.mirroring == 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        }
+    }
+
+    fn cpu_clock(&mut self) {
+        self.cycle = self.cycle.wrapping_add(1);
+    }
+
+    fn ppu_address(&mut self, addr: u16) {
+        let a12 = addr & 0x1000 > 0;
+
+        if a12 && !self.a12 && self.cycle.wrapping_sub(self.a12_low_since) >= 3 {
+            self.clock_irq();
+        }
+
+        if !a12 && self.a12 {
+            self.a12_low_since = self.cycle;
+        }
+
+        self.a12 = a12;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_rom::{TestRom, numbered_banks};
+
+    //128KB of PRG in numbered 8KB banks, and the IRQ line it's wired to
+    fn mapper(submapper: u8) -> (Mmc3, IrqLine) {
+        let irq = IrqLine::new();
+        let rom = TestRom::new(4).submapper(submapper).prg_rom(numbered_banks(16, 0x2000)).build();
+
+        (Mmc3::new(rom, irq.clone()), irq)
+    }
+
+    //A12 rising after being low long enough, like at the start of a scanline's sprite fetches
+    fn rise(mapper: &mut Mmc3) {
+        mapper.ppu_address(0x0000);
+        for _ in 0..8 {
+            mapper.cpu_clock();
+        }
+        mapper.ppu_address(0x1000);
+    }
+
+    //Latch, reload and enable
+    fn start(mapper: &mut Mmc3, latch: u8) {
+        mapper.cpu_write(0xC000, latch);
+        mapper.cpu_write(0xC001, 0);
+        mapper.cpu_write(0xE001, 0);
+    }
+
+    //Acknowledge and enable again
+    fn acknowledge(mapper: &mut Mmc3) {
+        mapper.cpu_write(0xE000, 0);
+        mapper.cpu_write(0xE001, 0);
+    }
+
+    #[test]
+    fn prg_banks_swap_with_bit_6() {
+        let (mut mapper, _) = mapper(0);
+        mapper.cpu_write(0x8000, 6);
+        mapper.cpu_write(0x8001, 5);
+        mapper.cpu_write(0x8000, 7);
+        mapper.cpu_write(0x8001, 6);
+
+        let banks: Vec<u8> = [0x8000, 0xA000, 0xC000, 0xE000].iter().map(|&addr| mapper.cpu_read(addr)).collect();
+        assert_eq!(banks, vec![5, 6, 14, 15]);
+
+        mapper.cpu_write(0x8000, 0x40);
+        let banks: Vec<u8> = [0x8000, 0xA000, 0xC000, 0xE000].iter().map(|&addr| mapper.cpu_read(addr)).collect();
+        assert_eq!(banks, vec![14, 6, 5, 15]);
+    }
+
+    //The fixed banks wrap around on an 8KB rom instead of underflowing
+    #[test]
+    fn single_bank_prg_wraps_around() {
+        let mut mapper = Mmc3::new(TestRom::new(4).prg_rom(vec![0xAA; 0x2000]).build(), IrqLine::new());
+
+        for &addr in &[0x8000, 0xA000, 0xC000, 0xE000] {
+            assert_eq!(mapper.cpu_read(addr), 0xAA);
+        }
+    }
+
+    //The first rise loads the latch, then each one counts down to 0
+    #[test]
+    fn irq_fires_when_the_counter_reaches_zero() {
+        let (mut mapper, irq) = mapper(0);
+        start(&mut mapper, 3);
+
+        for _ in 0..3 {
+            rise(&mut mapper);
+            assert!(!irq.is_asserted());
+        }
+
+        rise(&mut mapper);
+        assert!(irq.is_asserted_by(IrqSource::Mapper));
+    }
+
+    #[test]
+    fn quick_dips_in_a12_are_filtered_out() {
+        let (mut mapper, irq) = mapper(0);
+        start(&mut mapper, 1);
+        rise(&mut mapper);
+
+        mapper.ppu_address(0x0000);
+        mapper.cpu_clock();
+        mapper.ppu_address(0x1000);
+        assert!(!irq.is_asserted());
+
+        rise(&mut mapper);
+        assert!(irq.is_asserted());
+    }
+
+    //$C001 clears the counter, so the next rise loads the latch again
+    #[test]
+    fn c001_reloads_on_the_next_rise() {
+        let (mut mapper, irq) = mapper(0);
+        start(&mut mapper, 5);
+        rise(&mut mapper);
+        rise(&mut mapper);
+
+        mapper.cpu_write(0xC000, 1);
+        mapper.cpu_write(0xC001, 0);
+
+        rise(&mut mapper);
+        assert!(!irq.is_asserted());
+
+        rise(&mut mapper);
+        assert!(irq.is_asserted());
+    }
+
+    #[test]
+    fn e000_acknowledges_and_disables() {
+        let (mut mapper, irq) = mapper(0);
+        start(&mut mapper, 0);
+        rise(&mut mapper);
+        assert!(irq.is_asserted());
+
+        mapper.cpu_write(0xE000, 0);
+        assert!(!irq.is_asserted());
+
+        rise(&mut mapper);
+        assert!(!irq.is_asserted());
+
+        mapper.cpu_write(0xE001, 0);
+        rise(&mut mapper);
+        assert!(irq.is_asserted());
+    }
+
+    //With a latch of 0, the newer MMC3s (and the MMC6) fire on every rise,
+    //the MMC3A only on the one after a reload
+    #[test]
+    fn zero_latch_depends_on_the_chip() {
+        for &(submapper, fires) in &[(0, 3), (1, 3), (4, 1)] {
+            let (mut mapper, irq) = mapper(submapper);
+            start(&mut mapper, 0);
+
+            let mut count = 0;
+            for _ in 0..3 {
+                rise(&mut mapper);
+                if irq.is_asserted() {
+                    count += 1;
+                }
+                acknowledge(&mut mapper);
+            }
+
+            assert_eq!(count, fires, "submapper {}", submapper);
+        }
+    }
+}
@@ -0,0 +1,894 @@
+use super::{Mapper, Chr, prg_ram};
+use super::super::cart::{Rom, Mirroring};
+use super::super::irq::{IrqLine, IrqSource};
+
+//Where each line's fetches are, counting the ppu's reads from the start of the line.
+//128 for the 32 background tiles, 32 for the 8 sprites, then 8 for
+//the next line's first two tiles and two more nametable reads
+//https://wiki.nesdev.com/w/index.php/PPU_rendering
+const SPRITE_FETCHES: u16 = 128;
+const PREFETCHES: u16 = 160;
+const LINE_FETCHES: u16 = 170;
+
+//Our ppu hands its fetches over in a few batches a line, the longest gap between
+//them being most of a scanline, so the MMC5 waits a whole scanline of cpu cycles
+//without reads (not the real three) before it decides rendering has stopped
+const IDLE_CYCLES: u32 = 114;
+
+//Mapper 005, the MMC5. Four PRG and CHR banking modes, a second set of CHR banks
+//for the background when sprites are 8x16, 1KB of extra RAM (ExRAM) usable as a
+//nametable, as a bank and palette for every background tile, or as plain RAM,
+//a nametable filled with one tile, a vertical split, a scanline IRQ and a multiplier.
+//It works out what the ppu is doing by counting its reads, since it can't see the
+//dot it's on. The sound channels aren't emulated
+//https://wiki.nesdev.com/w/index.php/MMC5
+pub struct Mmc5 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    exram: Vec<u8>,
+
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2], //writes are only allowed while these are 2 and 1
+    exram_mode: u8,
+    nametables: u8, //two bits for each of the four, see read_nametable
+    fill_tile: u8,
+    fill_attribute: u8,
+
+    prg_banks: [u8; 5], //$5113-$5117, bit 7 picks ROM over RAM
+    chr_banks: [u16; 12], //$5120-$5127 (set A), then $5128-$512B (set B)
+    chr_upper: u8, //$5130, the top bits for the next CHR bank written
+    last_set_b: bool, //which set was written last, used outside of rendering
+    sprites_8x16: bool, //snooped from $2000
+
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+
+    irq: IrqLine,
+    irq_target: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline: u8,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    //Watching the ppu's bus. A new line is spotted by the same nametable byte
+    //read three times in a row, and fetches counts the reads since then
+    last_read: Option<u16>,
+    repeats: u8,
+    fetches: u16,
+    idle: u32,
+
+    //The background tile being fetched, for the pattern reads that follow its nametable read
+    tile_exram: u8,
+    tile_split: bool,
+    split_y: u8,
+}
+
+impl Mmc5 {
+    pub fn new(rom: Rom, irq: IrqLine) -> Mmc5 {
+        Mmc5 {
+            prg_ram: prg_ram(&rom.header, rom.trainer),
+            chr: Chr::new(rom.chr_rom, &rom.header),
+            prg_rom: rom.prg_rom,
+            exram: vec![0u8; 0x400],
+
+            //8KB PRG banks at power on, with the last bank at $E000 for the reset vector
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0, 0],
+            exram_mode: 0,
+            nametables: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+
+            prg_banks: [0, 0, 0, 0, 0xFF],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            last_set_b: false,
+            sprites_8x16: false,
+
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+
+            irq: irq,
+            irq_target: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline: 0,
+
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+
+            last_read: None,
+            repeats: 0,
+            fetches: 0,
+            idle: 0,
+
+            tile_exram: 0,
+            tile_split: false,
+            split_y: 0,
+        }
+    }
+
+    //Which 8KB bank an address in $6000-$FFFF is in, and whether it's ROM.
+    //Bigger banks ignore the low bits of their register
+    fn prg_bank(&self, addr: u16) -> (bool, usize) {
+        if addr < 0x8000 {
+            return (false, self.prg_banks[0] as usize)
+        }
+
+        //The register, and how many 8KB banks it switches at once
+        let (reg, size) = match (self.prg_mode, addr) {
+            (0, _) => (4, 4),
+            (1, 0x8000...0xBFFF) | (2, 0x8000...0xBFFF) => (2, 2),
+            (1, _) => (4, 2),
+            (2, 0xC000...0xDFFF) => (3, 1),
+            (2, _) => (4, 1),
+            _ => ((addr - 0x8000) as usize / 0x2000 + 1, 1),
+        };
+
+        let val = self.prg_banks[reg];
+        let rom = reg == 4 || val & 0x80 > 0;
+        let bank = (val & 0x7F) as usize & !(size - 1) | ((addr - 0x8000) as usize / 0x2000) & (size - 1);
+
+        (rom, bank)
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [2, 1]
+    }
+
+    //Set A is $5120-$5127, set B repeats $5128-$512B in both halves
+    fn chr_addr(&self, addr: u16, set_b: bool) -> usize {
+        let addr = addr as usize;
+
+        let (size, reg) = match self.chr_mode {
+            0 => (0x2000, 7),
+            1 => (0x1000, (addr / 0x1000) * 4 + 3),
+            2 => (0x0800, (addr / 0x0800) * 2 + 1),
+            _ => (0x0400, addr / 0x0400),
+        };
+
+        let reg = if set_b { 8 + (reg & 0x3) } else { reg };
+
+        self.chr_banks[reg] as usize * size + (addr & (size - 1))
+    }
+
+    //With 8x16 sprites, sprite patterns come from set A and the background from set B.
+    //Outside of rendering it's whichever was written last, with 8x8 sprites it's always A
+    fn chr_set_b(&self) -> bool {
+        if !self.sprites_8x16 {
+            false
+        } else if !self.in_frame {
+            self.last_set_b
+        } else {
+            self.fetches < SPRITE_FETCHES || self.fetches >= PREFETCHES
+        }
+    }
+
+    //Bit 7 is pending, bit 6 is in frame
+    fn irq_status(&self) -> u8 {
+        (if self.irq_pending { 0x80 } else { 0 }) | (if self.in_frame { 0x40 } else { 0 })
+    }
+
+    fn update_irq(&mut self) {
+        if self.irq_pending && self.irq_enabled {
+            self.irq.assert(IrqSource::Mapper);
+        } else {
+            self.irq.release(IrqSource::Mapper);
+        }
+    }
+
+    //Every read the ppu makes, to spot the start of a line. The first line
+    //of a frame starts the count over, every other one counts up towards the IRQ
+    //https://wiki.nesdev.com/w/index.php/MMC5#Scanline_Detection_and_Scanline_IRQ
+    fn watch_read(&mut self, addr: u16) {
+        self.idle = 0;
+
+        if self.last_read == Some(addr) && addr >= 0x2000 {
+            self.repeats = self.repeats.saturating_add(1);
+        } else {
+            self.repeats = 0;
+        }
+
+        self.last_read = Some(addr);
+
+        if self.repeats == 2 {
+            if self.in_frame {
+                self.scanline = self.scanline.wrapping_add(1);
+
+                if self.scanline == self.irq_target {
+                    self.irq_pending = true;
+                }
+            } else {
+                self.in_frame = true;
+                self.scanline = 0;
+                self.irq_pending = false;
+            }
+
+            self.update_irq();
+
+            //This is the line's first read
+            self.fetches = 0;
+        }
+    }
+
+    //Counts the ppu's reads, once the read is done with
+    fn count_read(&mut self) {
+        if self.fetches < LINE_FETCHES {
+            self.fetches += 1;
+        }
+    }
+
+    //The screen column and line of the background tile the ppu is fetching, if it is
+    fn background_tile(&self) -> Option<(u8, u8)> {
+        match self.fetches {
+            0...127 => Some(((self.fetches / 4) as u8 + 2, self.scanline)),
+            PREFETCHES...167 => Some((((self.fetches - PREFETCHES) / 4) as u8, self.scanline.wrapping_add(1))),
+            _ => None,
+        }
+    }
+
+    //The split is the columns left of the split tile (or from it rightwards),
+    //drawn from the nametable in ExRAM with its own vertical scroll and CHR bank
+    //https://wiki.nesdev.com/w/index.php/MMC5#Vertical_Split_Mode_($5200)
+    fn in_split(&self, column: u8) -> bool {
+        let tile = self.split_control & 0x1F;
+
+        self.split_control & 0x80 > 0 && self.exram_mode <= 1 && if self.split_control & 0x40 > 0 {
+            column >= tile
+        } else {
+            column < tile
+        }
+    }
+
+    //ExRAM as a nametable, which reads as zeros when it's used as plain RAM
+    fn exram_nametable(&self, addr: u16) -> u8 {
+        if self.exram_mode <= 1 {
+            self.exram[addr as usize & 0x3FF]
+        } else {
+            0
+        }
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let val = self.cpu_peek(addr).unwrap_or(0);
+
+        //Reading the status acknowledges the IRQ
+        if addr == 0x5204 {
+            self.irq_pending = false;
+            self.update_irq();
+        }
+
+        val
+    }
+
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x5100 => self.prg_mode = val & 0x3,
+            0x5101 => self.chr_mode = val & 0x3,
+            0x5102 => self.prg_ram_protect[0] = val & 0x3,
+            0x5103 => self.prg_ram_protect[1] = val & 0x3,
+            0x5104 => self.exram_mode = val & 0x3,
+            0x5105 => self.nametables = val,
+            0x5106 => self.fill_tile = val,
+            0x5107 => self.fill_attribute = val & 0x3,
+
+            0x5113...0x5117 => self.prg_banks[(addr - 0x5113) as usize] = val,
+
+            0x5120...0x512B => {
+                self.chr_banks[(addr - 0x5120) as usize] = val as u16 | (self.chr_upper as u16) << 8;
+                self.last_set_b = addr >= 0x5128;
+            }
+
+            0x5130 => self.chr_upper = val & 0x3,
+
+            0x5200 => self.split_control = val,
+            0x5201 => self.split_scroll = val,
+            0x5202 => self.split_bank = val,
+
+            0x5203 => self.irq_target = val,
+            0x5204 => {
+                self.irq_enabled = val & 0x80 > 0;
+                self.update_irq();
+            }
+
+            0x5205 => self.multiplicand = val,
+            0x5206 => self.multiplier = val,
+
+            //In the nametable and extended attribute modes the cpu can only
+            //write while the ppu is rendering, other writes store 0
+            0x5C00...0x5FFF => {
+                let val = match self.exram_mode {
+                    0 | 1 if !self.in_frame => 0,
+                    3 => return,
+                    _ => val,
+                };
+
+                self.exram[(addr - 0x5C00) as usize] = val;
+            }
+
+            0x6000...0xFFFF => {
+                let (rom, bank) = self.prg_bank(addr);
+
+                if !rom && self.prg_ram_writable() && self.prg_ram.len() > 0 {
+                    let len = self.prg_ram.len();
+                    self.prg_ram[(bank * 0x2000 + (addr as usize & 0x1FFF)) % len] = val;
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5204 => Some(self.irq_status()),
+
+            0x5205 => Some((self.multiplicand as u16 * self.multiplier as u16) as u8),
+            0x5206 => Some(((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8),
+
+            0x5C00...0x5FFF if self.exram_mode >= 2 => {
+                Some(self.exram[(addr - 0x5C00) as usize])
+            }
+
+            0x6000...0xFFFF => {
+                let (rom, bank) = self.prg_bank(addr);
+
+                if rom && self.prg_rom.len() > 0 {
+                    Some(self.prg_rom[(bank * 0x2000 + (addr as usize & 0x1FFF)) % self.prg_rom.len()])
+                } else if !rom && self.prg_ram.len() > 0 {
+                    Some(self.prg_ram[(bank * 0x2000 + (addr as usize & 0x1FFF)) % self.prg_ram.len()])
+                } else {
+                    None
+                }
+            }
+
+            _ => None,
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let background = self.in_frame && self.background_tile().is_some();
+
+        //The split has its own fine scroll, and extended attributes
+        //pick a 4KB bank for every tile
+        let addr = if background && self.tile_split {
+            self.split_bank as usize * 0x1000 + (addr as usize & 0x0FF8) + (self.split_y & 0x7) as usize
+        } else if background && self.exram_mode == 1 {
+            let bank = (self.tile_exram & 0x3F) as usize | (self.chr_upper as usize) << 6;
+            bank * 0x1000 + (addr as usize & 0x0FFF)
+        } else {
+            self.chr_addr(addr, self.chr_set_b())
+        };
+
+        self.chr.read(addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        let addr = self.chr_addr(addr, self.chr_set_b());
+        self.chr.write(addr, val);
+    }
+
+    //The closest of the usual arrangements, $5105 does the real work in read_nametable
+    fn mirroring(&self) -> Mirroring {
+        match self.nametables {
+            0x00 => Mirroring::SingleScreenLower,
+            0x55 => Mirroring::SingleScreenUpper,
+            0x44 => Mirroring::Vertical,
+            0x50 => Mirroring::Horizontal,
+            _ => Mirroring::FourScreen,
+        }
+    }
+
+    //$5105 picks what each nametable is: CIRAM's first or second page, ExRAM,
+    //or fill mode. Background tiles inside the split come from ExRAM instead
+    fn read_nametable(&mut self, addr: u16, ciram: &[u8; 0x1000]) -> u8 {
+        self.watch_read(addr);
+
+        let attribute = addr & 0x3FF >= 0x3C0;
+
+        //Nametable bytes start a tile, attribute bytes come after them
+        let tile = if self.in_frame { self.background_tile() } else { None };
+        if let (Some((column, line)), false) = (tile, attribute) {
+            self.tile_split = self.in_split(column);
+
+            if self.tile_split {
+                self.split_y = ((line as u16 + self.split_scroll as u16) % 240) as u8;
+            }
+        }
+
+        let val = match tile {
+            Some((column, _)) if self.tile_split => {
+                //The two tiles fetched past the right edge wrap around to the left
+                let row = (self.split_y / 8) as usize;
+                let column = column as usize % 32;
+
+                if attribute {
+                    self.exram[0x3C0 + (row / 4) * 8 + column / 4]
+                } else {
+                    self.exram[row * 32 + column]
+                }
+            }
+
+            //Each tile's ExRAM byte has its palette in the top two bits
+            Some(_) if attribute && self.exram_mode == 1 => {
+                (self.tile_exram >> 6) * 0x55
+            }
+
+            _ => {
+                let nametable = (addr >> 10) & 0x3;
+
+                let val = match (self.nametables >> (nametable * 2)) & 0x3 {
+                    0 => ciram[addr as usize & 0x3FF],
+                    1 => ciram[0x400 + (addr as usize & 0x3FF)],
+                    2 => self.exram_nametable(addr),
+                    _ if attribute => self.fill_attribute * 0x55,
+                    _ => self.fill_tile,
+                };
+
+                if !attribute {
+                    self.tile_exram = self.exram[addr as usize & 0x3FF];
+                }
+
+                val
+            }
+        };
+
+        self.count_read();
+
+        val
+    }
+
+    fn write_nametable(&mut self, addr: u16, val: u8, ciram: &mut [u8; 0x1000]) {
+        let nametable = (addr >> 10) & 0x3;
+
+        match (self.nametables >> (nametable * 2)) & 0x3 {
+            0 => ciram[addr as usize & 0x3FF] = val,
+            1 => ciram[0x400 + (addr as usize & 0x3FF)] = val,
+            2 if self.exram_mode <= 1 => self.exram[addr as usize & 0x3FF] = val,
+            _ => {}
+        }
+    }
+
+    fn ppu_register_write(&mut self, addr: u16, val: u8) {
+        if addr == 0x2000 {
+            self.sprites_8x16 = val & 0x20 > 0;
+        }
+    }
+
+    fn cpu_clock(&mut self) {
+        if self.idle < IDLE_CYCLES {
+            self.idle += 1;
+
+            if self.idle == IDLE_CYCLES {
+                self.in_frame = false;
+                self.last_read = None;
+                self.repeats = 0;
+            }
+        }
+    }
+
+    fn ppu_address(&mut self, addr: u16) {
+        self.watch_read(addr);
+        self.count_read();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_rom::{TestRom, numbered_banks};
+    use super::super::super::cart::NESCart;
+    use super::super::super::interconnect::VideoBus;
+    use super::super::super::ppu::{NESPpu, WIDTH};
+
+    //The cart next to the real ppu, clocked the way Interconnect::tick does,
+    //so the mapper sees the ppu's fetches batched up the way they really are
+    struct Console {
+        ppu: NESPpu,
+        cart: NESCart,
+        ciram: [u8; 0x1000],
+        irq: IrqLine,
+    }
+
+    impl Console {
+        //Background rendering on, stopped at the start of the first full frame
+        fn new(rom: TestRom) -> Console {
+            let irq = IrqLine::new();
+
+            let mut console = Console {
+                ppu: NESPpu::new(),
+                cart: NESCart::from_bytes(&rom.bytes(), irq.clone()).unwrap(),
+                ciram: [0u8; 0x1000],
+                irq: irq,
+            };
+
+            console.write(0x2001, 0x08);
+            console.run_to(-1, 0);
+
+            console
+        }
+
+        fn write(&mut self, addr: u16, val: u8) {
+            match addr {
+                0x2000...0x2007 => {
+                    self.cart.ppu_register_write(addr, val);
+                    self.ppu.write_ppu((addr - 0x2000) as usize, val, &mut VideoBus::new(&mut self.cart, &mut self.ciram));
+                }
+
+                _ => self.cart.cpu_write(addr, val),
+            }
+        }
+
+        fn tick(&mut self) {
+            self.cart.cpu_clock();
+
+            for _ in 0..3 {
+                self.ppu.step(&mut VideoBus::new(&mut self.cart, &mut self.ciram));
+            }
+        }
+
+        //The next time the ppu gets to the dot on that line
+        fn run_to(&mut self, scanline: i16, dot: u16) {
+            self.tick();
+
+            while self.ppu.scanline() != scanline || self.ppu.dot() < dot {
+                self.tick();
+            }
+        }
+
+        fn status(&self) -> u8 {
+            self.cart.cpu_peek(0x5204).unwrap()
+        }
+
+        fn pixel(&self, x: usize, y: usize) -> u32 {
+            self.ppu.frame()[y*WIDTH + x]
+        }
+    }
+
+    //4KB CHR banks, each filled with one byte
+    fn chr(banks: &[u8]) -> Vec<u8> {
+        banks.iter().flat_map(|&val| vec![val; 0x1000]).collect()
+    }
+
+    fn mapper(rom: TestRom) -> Mmc5 {
+        Mmc5::new(rom.build(), IrqLine::new())
+    }
+
+    #[test]
+    fn irq_fires_on_the_target_scanline() {
+        let mut console = Console::new(TestRom::new(5));
+        console.write(0x5203, 100);
+        console.write(0x5204, 0x80);
+
+        console.run_to(99, 338);
+        assert!(!console.irq.is_asserted());
+
+        console.run_to(100, 4);
+        assert!(console.irq.is_asserted_by(IrqSource::Mapper));
+        assert_eq!(console.status() & 0x80, 0x80);
+
+        //Reading the status acknowledges it
+        console.cart.cpu_read(0x5204);
+        assert!(!console.irq.is_asserted());
+        assert_eq!(console.status() & 0x80, 0);
+    }
+
+    #[test]
+    fn irq_waits_for_5204_to_be_enabled() {
+        let mut console = Console::new(TestRom::new(5));
+        console.write(0x5203, 20);
+
+        console.run_to(30, 0);
+        assert!(!console.irq.is_asserted());
+        assert_eq!(console.status() & 0x80, 0x80);
+
+        console.write(0x5204, 0x80);
+        assert!(console.irq.is_asserted());
+    }
+
+    //The ppu's fetches are bunched up a few times a line. If the gaps
+    //between them ever grow past IDLE_CYCLES, the frame would end in the
+    //middle and the count would start over on the wrong line
+    #[test]
+    fn in_frame_lasts_the_visible_lines_of_every_frame() {
+        let mut console = Console::new(TestRom::new(5));
+        console.write(0x5203, 200);
+        console.write(0x5204, 0x80);
+
+        for _ in 0..3 {
+            for line in 0..240 {
+                console.run_to(line, 338);
+                assert_eq!(console.status() & 0x40, 0x40, "line {}", line);
+            }
+
+            assert!(console.irq.is_asserted());
+            console.cart.cpu_read(0x5204);
+
+            console.run_to(242, 0);
+            assert_eq!(console.status() & 0x40, 0);
+        }
+    }
+
+    #[test]
+    fn turning_rendering_off_ends_the_frame() {
+        let mut console = Console::new(TestRom::new(5));
+
+        console.run_to(50, 0);
+        assert_eq!(console.status() & 0x40, 0x40);
+
+        console.write(0x2001, 0);
+        console.run_to(52, 0);
+        assert_eq!(console.status() & 0x40, 0);
+    }
+
+    //Set A is written last, but with 8x16 sprites the background comes from set B
+    #[test]
+    fn background_uses_set_b_with_8x16_sprites() {
+        for &(ctrl, color) in &[(0x00, 0), (0x20, 0xFF0000)] {
+            let mut console = Console::new(TestRom::new(5).chr_rom(chr(&[0x00, 0x00, 0xFF, 0xFF])));
+            console.write(0x512B, 1);
+            console.write(0x5127, 0);
+            console.write(0x2000, ctrl);
+
+            console.run_to(21, 0);
+            assert_eq!(console.pixel(100, 20), color, "ctrl {:02X}", ctrl);
+        }
+    }
+
+    //The split's on the ten columns on the left, with its own CHR bank
+    #[test]
+    fn vertical_split_draws_from_its_own_bank() {
+        let mut console = Console::new(TestRom::new(5).chr_rom(chr(&[0x00, 0xFF])));
+        console.write(0x5200, 0x80 | 10);
+        console.write(0x5202, 1);
+
+        console.run_to(21, 0);
+        assert_eq!(console.pixel(0, 20), 0xFF0000);
+        assert_eq!(console.pixel(79, 20), 0xFF0000);
+        assert_eq!(console.pixel(80, 20), 0);
+        assert_eq!(console.pixel(255, 20), 0);
+
+        //From the split tile rightwards instead
+        console.write(0x5200, 0xC0 | 10);
+        console.run_to(-1, 0);
+        console.run_to(21, 0);
+        assert_eq!(console.pixel(79, 20), 0);
+        assert_eq!(console.pixel(80, 20), 0xFF0000);
+    }
+
+    //Each tile's ExRAM byte picks a 4KB bank for it, ExRAM can only be written while rendering
+    #[test]
+    fn extended_attributes_pick_a_bank_for_each_tile() {
+        let mut console = Console::new(TestRom::new(5).chr_rom(chr(&[0x00, 0xFF])));
+        console.write(0x5104, 1);
+
+        console.run_to(10, 100);
+        console.write(0x5C00 + 2*32 + 5, 1);
+
+        console.run_to(21, 0);
+        assert_eq!(console.pixel(39, 20), 0);
+        assert_eq!(console.pixel(40, 20), 0xFF0000);
+        assert_eq!(console.pixel(47, 20), 0xFF0000);
+        assert_eq!(console.pixel(48, 20), 0);
+    }
+
+    #[test]
+    fn sprite_fetches_use_set_a_with_8x16_sprites() {
+        let mut mapper = mapper(TestRom::new(5).chr_rom(numbered_banks(16, 0x400)));
+        let ciram = [0u8; 0x1000];
+        mapper.cpu_write(0x5101, 3);
+        mapper.cpu_write(0x5120, 1);
+        mapper.cpu_write(0x5128, 2);
+        mapper.ppu_register_write(0x2000, 0x20);
+
+        //A new line, then the background tiles
+        for _ in 0..3 {
+            mapper.read_nametable(0x2000, &ciram);
+        }
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+
+        for _ in 1..SPRITE_FETCHES {
+            mapper.ppu_address(0x0000);
+        }
+        assert_eq!(mapper.ppu_read(0x0000), 1);
+
+        for _ in SPRITE_FETCHES..PREFETCHES {
+            mapper.ppu_address(0x0000);
+        }
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+    }
+
+    //Outside of rendering it's whichever set was written last, as long as sprites are 8x16
+    #[test]
+    fn chr_set_outside_of_rendering() {
+        let mut mapper = mapper(TestRom::new(5).chr_rom(numbered_banks(16, 0x400)));
+        mapper.cpu_write(0x5101, 3);
+        mapper.cpu_write(0x5128, 2);
+        mapper.cpu_write(0x5120, 1);
+        mapper.cpu_write(0x5128, 2);
+        assert_eq!(mapper.ppu_read(0x0000), 1);
+
+        mapper.ppu_register_write(0x2000, 0x20);
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+        assert_eq!(mapper.ppu_read(0x1000), 2);
+
+        mapper.cpu_write(0x5120, 1);
+        assert_eq!(mapper.ppu_read(0x0000), 1);
+    }
+
+    #[test]
+    fn chr_modes() {
+        let mut mapper = mapper(TestRom::new(5).chr_rom(numbered_banks(64, 0x400)));
+        for reg in 0..8 {
+            mapper.cpu_write(0x5120 + reg, 10 + reg as u8);
+        }
+
+        //1KB banks
+        mapper.cpu_write(0x5101, 3);
+        assert_eq!(mapper.ppu_read(0x0000), 10);
+        assert_eq!(mapper.ppu_read(0x1C00), 17);
+
+        //2KB banks from $5121, $5123...
+        mapper.cpu_write(0x5101, 2);
+        assert_eq!(mapper.ppu_read(0x0000), 22);
+        assert_eq!(mapper.ppu_read(0x0400), 23);
+        assert_eq!(mapper.ppu_read(0x1800), 2*17);
+
+        //4KB banks from $5123 and $5127
+        mapper.cpu_write(0x5101, 1);
+        mapper.cpu_write(0x5123, 1);
+        mapper.cpu_write(0x5127, 3);
+        assert_eq!(mapper.ppu_read(0x0C00), 7);
+        assert_eq!(mapper.ppu_read(0x1000), 12);
+
+        //An 8KB bank from $5127
+        mapper.cpu_write(0x5101, 0);
+        assert_eq!(mapper.ppu_read(0x0000), 24);
+        assert_eq!(mapper.ppu_read(0x1C00), 31);
+    }
+
+    #[test]
+    fn prg_modes() {
+        let mut mapper = mapper(TestRom::new(5).prg_rom(numbered_banks(16, 0x2000)));
+        let banks = |mapper: &Mmc5| -> Vec<u8> {
+            [0x8000, 0xA000, 0xC000, 0xE000].iter().map(|&addr| mapper.cpu_peek(addr).unwrap()).collect()
+        };
+
+        //8KB banks, with the last one at $E000 at power on
+        mapper.cpu_write(0x5114, 0x81);
+        mapper.cpu_write(0x5115, 0x82);
+        mapper.cpu_write(0x5116, 0x83);
+        assert_eq!(banks(&mapper), vec![1, 2, 3, 15]);
+
+        //16KB at $8000 from $5115, 8KB at $C000 from $5116, 8KB at $E000 from $5117
+        mapper.cpu_write(0x5100, 2);
+        mapper.cpu_write(0x5115, 0x85);
+        mapper.cpu_write(0x5116, 0x89);
+        assert_eq!(banks(&mapper), vec![4, 5, 9, 15]);
+
+        //Two 16KB banks from $5115 and $5117
+        mapper.cpu_write(0x5100, 1);
+        mapper.cpu_write(0x5117, 0x8D);
+        assert_eq!(banks(&mapper), vec![4, 5, 12, 13]);
+
+        //One 32KB bank from $5117
+        mapper.cpu_write(0x5100, 0);
+        mapper.cpu_write(0x5117, 0x86);
+        assert_eq!(banks(&mapper), vec![4, 5, 6, 7]);
+    }
+
+    //Bit 7 clear banks in PRG RAM, which is only writable with $5102 = 2 and $5103 = 1
+    #[test]
+    fn prg_ram_banks_and_write_protection() {
+        let mut mapper = mapper(TestRom::new(5).prg_ram(0x4000));
+        mapper.cpu_write(0x6000, 0x11);
+        assert_eq!(mapper.cpu_peek(0x6000), Some(0));
+
+        mapper.cpu_write(0x5102, 2);
+        mapper.cpu_write(0x5103, 1);
+        mapper.cpu_write(0x5113, 1);
+        mapper.cpu_write(0x6000, 0x11);
+
+        mapper.cpu_write(0x5114, 0x01);
+        assert_eq!(mapper.cpu_peek(0x8000), Some(0x11));
+
+        mapper.cpu_write(0x5113, 0);
+        assert_eq!(mapper.cpu_peek(0x6000), Some(0));
+    }
+
+    #[test]
+    fn multiplier() {
+        let mut mapper = mapper(TestRom::new(5));
+        assert_eq!(mapper.cpu_read(0x5205), 0x01);
+        assert_eq!(mapper.cpu_read(0x5206), 0xFE);
+
+        mapper.cpu_write(0x5205, 200);
+        mapper.cpu_write(0x5206, 200);
+        assert_eq!(mapper.cpu_read(0x5205), 0x40);
+        assert_eq!(mapper.cpu_read(0x5206), 0x9C);
+    }
+
+    //The cpu can't see ExRAM in modes 0 and 1, and only writes zeros to it outside of rendering.
+    //Mode 2 is plain RAM, and mode 3 is read only
+    #[test]
+    fn exram_modes_from_the_cpu() {
+        let mut mapper = mapper(TestRom::new(5));
+
+        mapper.cpu_write(0x5C00, 0x42);
+        assert_eq!(mapper.cpu_peek(0x5C00), None);
+
+        mapper.cpu_write(0x5104, 2);
+        assert_eq!(mapper.cpu_peek(0x5C00), Some(0));
+        mapper.cpu_write(0x5C00, 0x42);
+        assert_eq!(mapper.cpu_peek(0x5C00), Some(0x42));
+
+        mapper.cpu_write(0x5104, 3);
+        mapper.cpu_write(0x5C00, 0x24);
+        assert_eq!(mapper.cpu_peek(0x5C00), Some(0x42));
+
+        mapper.cpu_write(0x5104, 1);
+        mapper.cpu_write(0x5C00, 0x24);
+        mapper.cpu_write(0x5104, 2);
+        assert_eq!(mapper.cpu_peek(0x5C00), Some(0));
+    }
+
+    //ExRAM is a nametable in modes 0 and 1, and reads back as zeros in the others
+    #[test]
+    fn exram_as_a_nametable() {
+        let mut mapper = mapper(TestRom::new(5));
+        let mut ciram = [0u8; 0x1000];
+        mapper.cpu_write(0x5105, 0xAA);
+
+        mapper.write_nametable(0x2405, 0x42, &mut ciram);
+        assert_eq!(mapper.read_nametable(0x2005, &ciram), 0x42);
+        assert_eq!(mapper.read_nametable(0x2C05, &ciram), 0x42);
+        assert_eq!(ciram[0x005], 0);
+
+        mapper.cpu_write(0x5104, 2);
+        assert_eq!(mapper.read_nametable(0x2005, &ciram), 0);
+        assert_eq!(mapper.cpu_peek(0x5C05), Some(0x42));
+    }
+
+    //Each nametable picks CIRAM's first or second page on its own
+    #[test]
+    fn nametables_from_ciram() {
+        let mut mapper = mapper(TestRom::new(5));
+        let mut ciram = [0u8; 0x1000];
+        ciram[0x010] = 1;
+        ciram[0x410] = 2;
+        mapper.cpu_write(0x5105, 0b00_01_01_00);
+
+        let tiles: Vec<u8> = [0x2010, 0x2410, 0x2810, 0x2C10].iter().map(|&addr| mapper.read_nametable(addr, &ciram)).collect();
+        assert_eq!(tiles, vec![1, 2, 2, 1]);
+        assert_eq!(mapper.mirroring(), Mirroring::FourScreen);
+
+        mapper.write_nametable(0x2810, 3, &mut ciram);
+        assert_eq!(ciram[0x410], 3);
+    }
+
+    //Every tile is $5106 and every attribute is $5107, writes go nowhere
+    #[test]
+    fn fill_mode() {
+        let mut mapper = mapper(TestRom::new(5));
+        let mut ciram = [0u8; 0x1000];
+        mapper.cpu_write(0x5105, 0xFF);
+        mapper.cpu_write(0x5106, 0x33);
+        mapper.cpu_write(0x5107, 2);
+
+        mapper.write_nametable(0x2005, 0x11, &mut ciram);
+        assert_eq!(mapper.read_nametable(0x2005, &ciram), 0x33);
+        assert_eq!(mapper.read_nametable(0x2FC0, &ciram), 0xAA);
+        assert!(ciram.iter().all(|&val| val == 0));
+    }
+}
@@ -0,0 +1,335 @@
+mod nrom;
+mod mmc1;
+mod mmc2;
+mod mmc3;
+mod mmc5;
+mod discrete;
+
+use super::cart::{Rom, CartHeader, Mirroring, CartError};
+use super::irq::IrqLine;
+
+use self::nrom::Nrom;
+use self::mmc1::Mmc1;
+use self::mmc2::Mmc2;
+use self::mmc3::Mmc3;
+use self::mmc5::Mmc5;
+use self::discrete::Discrete;
+
+//The board inside a cartridge. Decides what's at $4020-$FFFF on the cpu's bus
+//and $0000-$1FFF on the ppu's, and how the nametables are mirrored.
+//Boards that raise IRQs are handed the /IRQ line when they're built and assert
+//it as IrqSource::Mapper. The clocks are for boards that count things,
+//they do nothing by default
+//https://wiki.nesdev.com/w/index.php/Mapper
+pub trait Mapper {
+    fn cpu_read(&mut self, addr: u16) -> u8;
+    fn cpu_write(&mut self, addr: u16, val: u8);
+
+    //A read without side effects, for debuggers and logs
+    fn cpu_peek(&self, addr: u16) -> Option<u8>;
+
+    fn ppu_read(&mut self, addr: u16) -> u8;
+    fn ppu_write(&mut self, addr: u16, val: u8);
+
+    fn mirroring(&self) -> Mirroring;
+
+    //The nametables at $2000-$2FFF, which are the console's VRAM
+    //arranged by the mirroring unless the board has its own
+    fn read_nametable(&mut self, addr: u16, ciram: &[u8; 0x1000]) -> u8 {
+        ciram[self.mirroring().nametable_addr(addr)]
+    }
+
+    fn write_nametable(&mut self, addr: u16, val: u8, ciram: &mut [u8; 0x1000]) {
+        ciram[self.mirroring().nametable_addr(addr)] = val;
+    }
+
+    //Writes to the ppu's registers, for boards that listen in on them
+    fn ppu_register_write(&mut self, _addr: u16, _val: u8) {}
+
+    //Once every cpu cycle
+    fn cpu_clock(&mut self) {}
+
+    //Every address the ppu puts on its bus while rendering, for boards
+    //that watch it (like the MMC3 counting rises of A12)
+    fn ppu_address(&mut self, _addr: u16) {}
+}
+
+//Builds the board a rom was made for
+//https://wiki.nesdev.com/w/index.php/List_of_mappers
+pub fn new(rom: Rom, irq: IrqLine) -> Result<Box<dyn Mapper>, CartError> {
+    match rom.header.mapper {
+        0 => Ok(Box::new(Nrom::new(rom))),
+        1 => Ok(Box::new(Mmc1::new(rom))),
+        2 | 3 | 7 | 11 | 34 | 66 | 71 => Ok(Box::new(Discrete::new(rom))),
+        4 => Ok(Box::new(Mmc3::new(rom, irq))),
+        5 => Ok(Box::new(Mmc5::new(rom, irq))),
+        9 | 10 => Ok(Box::new(Mmc2::new(rom))),
+
+        mapper => Err(CartError::UnsupportedMapper { mapper: mapper, submapper: rom.header.submapper }),
+    }
+}
+
+//The pattern table memory on the cart. CHR ROM, or CHR RAM
+//(8KB unless the header says otherwise) when there's no CHR ROM
+pub struct Chr {
+    data: Vec<u8>,
+    ram: bool,
+}
+
+impl Chr {
+    pub fn new(chr_rom: Vec<u8>, header: &CartHeader) -> Chr {
+        if chr_rom.len() > 0 {
+            Chr { data: chr_rom, ram: false }
+        } else {
+            let size = (header.chr_ram_size + header.chr_nvram_size).max(0x2000);
+            Chr { data: vec![0u8; size], ram: true }
+        }
+    }
+
+    //Addresses past the end wrap around, like the unconnected
+    //upper address lines on a smaller chip
+    pub fn read(&self, addr: usize) -> u8 {
+        self.data[addr % self.data.len()]
+    }
+
+    pub fn write(&mut self, addr: usize, val: u8) {
+        if self.ram {
+            let len = self.data.len();
+            self.data[addr % len] = val;
+        }
+    }
+}
+
+//PRG RAM for $6000-$7FFF, sized by the header. Carts with a trainer
+//get it copied to $7000, and always have at least 8KB for it
+pub fn prg_ram(header: &CartHeader, trainer: Option<Vec<u8>>) -> Vec<u8> {
+    let mut size = header.prg_ram_size + header.prg_nvram_size;
+    if trainer.is_some() {
+        size = size.max(0x2000);
+    }
+
+    let mut ram = vec![0u8; size];
+
+    if let Some(trainer) = trainer {
+        ram[0x1000..(0x1000 + trainer.len())].copy_from_slice(&trainer);
+    }
+
+    ram
+}
+
+//An empty cartridge slot, reads are open bus
+pub struct NoCart;
+
+impl Mapper for NoCart {
+    fn cpu_read(&mut self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn cpu_write(&mut self, _addr: u16, _val: u8) {}
+
+    fn cpu_peek(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    fn ppu_read(&mut self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn ppu_write(&mut self, _addr: u16, _val: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+}
+
+//Rom images for the mapper tests. The headers are NES 2.0, so every size comes out
+//exactly as it was set, using the exponent form when it isn't a whole number of units
+#[cfg(test)]
+pub mod test_rom {
+    use super::super::cart::Rom;
+
+    pub struct TestRom {
+        mapper: u16,
+        submapper: u8,
+        flags: u8, //byte 6's mirroring, battery, trainer and four screen bits
+
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        trainer: Vec<u8>,
+
+        prg_ram_size: usize,
+        prg_nvram_size: usize,
+        chr_ram_size: usize,
+    }
+
+    impl TestRom {
+        //32KB of PRG ROM and 8KB of CHR ROM, all zeroes, and no RAM
+        pub fn new(mapper: u16) -> TestRom {
+            TestRom {
+                mapper: mapper,
+                submapper: 0,
+                flags: 0,
+
+                prg_rom: vec![0u8; 0x8000],
+                chr_rom: vec![0u8; 0x2000],
+                trainer: Vec::new(),
+
+                prg_ram_size: 0,
+                prg_nvram_size: 0,
+                chr_ram_size: 0,
+            }
+        }
+
+        pub fn submapper(mut self, submapper: u8) -> TestRom {
+            self.submapper = submapper;
+            self
+        }
+
+        pub fn prg_rom(mut self, data: Vec<u8>) -> TestRom {
+            self.prg_rom = data;
+            self
+        }
+
+        pub fn chr_rom(mut self, data: Vec<u8>) -> TestRom {
+            self.chr_rom = data;
+            self
+        }
+
+        //Takes the place of CHR ROM
+        pub fn chr_ram(mut self, size: usize) -> TestRom {
+            self.chr_rom = Vec::new();
+            self.chr_ram_size = size;
+            self
+        }
+
+        pub fn prg_ram(mut self, size: usize) -> TestRom {
+            self.prg_ram_size = size;
+            self
+        }
+
+        pub fn prg_nvram(mut self, size: usize) -> TestRom {
+            self.prg_nvram_size = size;
+            self.flags |= 0x2;
+            self
+        }
+
+        pub fn trainer(mut self, data: Vec<u8>) -> TestRom {
+            self.trainer = data;
+            self.flags |= 0x4;
+            self
+        }
+
+        pub fn vertical(mut self) -> TestRom {
+            self.flags |= 0x1;
+            self
+        }
+
+        pub fn header(&self) -> Vec<u8> {
+            let (prg_lo, prg_hi) = rom_size(self.prg_rom.len(), 0x4000);
+            let (chr_lo, chr_hi) = rom_size(self.chr_rom.len(), 0x2000);
+
+            vec![
+                0x4E, 0x45, 0x53, 0x1A,
+                prg_lo,
+                chr_lo,
+                ((self.mapper as u8 & 0x0F) << 4) | self.flags,
+                (self.mapper as u8 & 0xF0) | 0x08,
+                (self.submapper << 4) | (self.mapper >> 8) as u8,
+                (chr_hi << 4) | prg_hi,
+                (ram_shift(self.prg_nvram_size) << 4) | ram_shift(self.prg_ram_size),
+                ram_shift(self.chr_ram_size),
+                0, 0, 0, 0,
+            ]
+        }
+
+        pub fn bytes(&self) -> Vec<u8> {
+            let mut data = self.header();
+            data.extend(&self.trainer);
+            data.extend(&self.prg_rom);
+            data.extend(&self.chr_rom);
+
+            data
+        }
+
+        pub fn build(&self) -> Rom {
+            Rom::parse(&self.bytes()).unwrap()
+        }
+    }
+
+    //count banks of size bytes, each filled with its own number
+    pub fn numbered_banks(count: usize, size: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        for bank in 0..count {
+            data.extend(vec![bank as u8; size]);
+        }
+
+        data
+    }
+
+    //The low byte and high nibble for a ROM size, see CartHeader::rom_size
+    fn rom_size(len: usize, unit: usize) -> (u8, u8) {
+        if len % unit == 0 && len / unit < 0xF00 {
+            return ((len / unit) as u8, (len / unit >> 8) as u8)
+        }
+
+        for exponent in 0..0x40 {
+            for multiplier in 0..4 {
+                if (1usize << exponent).checked_mul(multiplier * 2 + 1) == Some(len) {
+                    return (((exponent << 2) | multiplier) as u8, 0x0F)
+                }
+            }
+        }
+
+        panic!("{} bytes can't be written in an NES 2.0 header", len)
+    }
+
+    //64 << shift bytes, 0 for none
+    fn ram_shift(size: usize) -> u8 {
+        if size == 0 {
+            return 0
+        }
+
+        assert!(size.is_power_of_two() && size >= 128, "{} bytes of RAM can't be written in an NES 2.0 header", size);
+        (size / 64).trailing_zeros() as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_rom::TestRom;
+    use super::super::irq::IrqLine;
+
+    //Every board comes up with the last PRG bank at $E000, where the reset vector is
+    #[test]
+    fn builds_every_supported_mapper() {
+        for &number in &[0, 1, 2, 3, 4, 5, 7, 9, 10, 11, 34, 66, 71] {
+            let mut prg_rom = vec![0u8; 0x8000];
+            prg_rom[0x7FFF] = 0x42;
+
+            match new(TestRom::new(number).prg_rom(prg_rom).build(), IrqLine::new()) {
+                Ok(mut mapper) => assert_eq!(mapper.cpu_read(0xFFFF), 0x42, "mapper {}", number),
+                Err(why) => panic!("mapper {}: {}", number, why),
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_mapper_is_an_error() {
+        match new(TestRom::new(99).submapper(2).build(), IrqLine::new()) {
+            Err(CartError::UnsupportedMapper { mapper: 99, submapper: 2 }) => {}
+            Err(why) => panic!("wrong error: {}", why),
+            Ok(_) => panic!("mapper 99 isn't supported"),
+        }
+    }
+
+    #[test]
+    fn empty_slot_is_open_bus() {
+        let mut cart = NoCart;
+        cart.cpu_write(0x8000, 0x42);
+
+        assert_eq!(cart.cpu_peek(0x8000), None);
+        assert_eq!(cart.cpu_read(0x8000), 0);
+        assert_eq!(cart.ppu_read(0x0000), 0);
+    }
+}
@@ -0,0 +1,148 @@
+use super::{Mapper, Chr, prg_ram};
+use super::super::cart::{Rom, Mirroring};
+
+//Mapper 000, no bank switching at all. NROM-128 has 16KB of PRG ROM, mirrored
+//at $8000 and $C000, NROM-256 fills both with 32KB. CHR is ROM, or RAM on
+//a few homebrew boards, and the mirroring is soldered on.
+//Family Basic adds PRG RAM at $6000
+//https://wiki.nesdev.com/w/index.php/NROM
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(rom: Rom) -> Nrom {
+        Nrom {
+            prg_ram: prg_ram(&rom.header, rom.trainer),
+            chr: Chr::new(rom.chr_rom, &rom.header),
+            prg_rom: rom.prg_rom,
+
+            mirroring: if rom.header.four_screen { Mirroring::FourScreen } else { rom.header.mirroring },
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.cpu_peek(addr).unwrap_or(0)
+    }
+
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x6000...0x7FFF if self.prg_ram.len() > 0 => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = val;
+            }
+
+            _ => {}
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000...0x7FFF if self.prg_ram.len() > 0 => {
+                Some(self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()])
+            }
+
+            //A 16KB rom only uses the low 14 address lines, so it shows up twice
+            0x8000...0xFFFF if self.prg_rom.len() > 0 => {
+                Some(self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()])
+            }
+
+            _ => None,
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        self.chr.write(addr as usize, val);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_rom::{TestRom, numbered_banks};
+
+    #[test]
+    fn nrom_128_is_mirrored_at_c000() {
+        let mut mapper = Nrom::new(TestRom::new(0).prg_rom(numbered_banks(1, 0x4000)).build());
+        mapper.prg_rom[0x0123] = 0x42;
+
+        assert_eq!(mapper.cpu_read(0x8123), 0x42);
+        assert_eq!(mapper.cpu_read(0xC123), 0x42);
+    }
+
+    #[test]
+    fn nrom_256_fills_both_halves() {
+        let mut mapper = Nrom::new(TestRom::new(0).prg_rom(numbered_banks(2, 0x4000)).build());
+
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        assert_eq!(mapper.cpu_read(0xBFFF), 0);
+        assert_eq!(mapper.cpu_read(0xC000), 1);
+        assert_eq!(mapper.cpu_read(0xFFFF), 1);
+    }
+
+    #[test]
+    fn trainer_is_loaded_at_7000() {
+        let mut mapper = Nrom::new(TestRom::new(0).trainer(vec![0x42; 512]).build());
+
+        assert_eq!(mapper.cpu_read(0x6FFF), 0);
+        assert_eq!(mapper.cpu_read(0x7000), 0x42);
+        assert_eq!(mapper.cpu_read(0x71FF), 0x42);
+        assert_eq!(mapper.cpu_read(0x7200), 0);
+    }
+
+    #[test]
+    fn chr_ram_is_writable() {
+        let mut mapper = Nrom::new(TestRom::new(0).chr_ram(0x2000).build());
+
+        mapper.ppu_write(0x1234, 0x42);
+        assert_eq!(mapper.ppu_read(0x1234), 0x42);
+    }
+
+    #[test]
+    fn chr_rom_ignores_writes() {
+        let mut mapper = Nrom::new(TestRom::new(0).chr_rom(vec![0x11; 0x2000]).build());
+
+        mapper.ppu_write(0x1234, 0x42);
+        assert_eq!(mapper.ppu_read(0x1234), 0x11);
+    }
+
+    #[test]
+    fn prg_ram_when_the_board_has_it() {
+        let mut mapper = Nrom::new(TestRom::new(0).prg_ram(0x2000).build());
+
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_peek(0x6000), Some(0x42));
+    }
+
+    //Nothing answers at $6000-$7FFF, and writes there don't reach PRG ROM
+    #[test]
+    fn no_prg_ram_is_open_bus() {
+        let mut mapper = Nrom::new(TestRom::new(0).build());
+
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_peek(0x6000), None);
+
+        mapper.cpu_write(0x8000, 0x42);
+        assert_eq!(mapper.cpu_peek(0x8000), Some(0));
+    }
+
+    #[test]
+    fn mirroring_comes_from_the_header() {
+        assert_eq!(Nrom::new(TestRom::new(0).build()).mirroring(), Mirroring::Horizontal);
+        assert_eq!(Nrom::new(TestRom::new(0).vertical().build()).mirroring(), Mirroring::Vertical);
+    }
+}
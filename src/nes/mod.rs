@@ -1,26 +1,43 @@
 mod cpu;
 mod ppu;
 mod apu;
+mod irq;
+mod trace;
+pub mod nestest;
+pub mod single_step;
+pub mod klaus;
+pub mod bench;
+mod json;
+mod bus;
 mod io;
 mod cart;
+mod mapper;
 mod interconnect;
 mod opcode;
 mod integer_casting;
 
-use self::cpu::*;
 use self::interconnect::*;
+
+pub use self::cpu::{NESCpu, CPUStatus, ExecutionMode, CpuVariant, CpuError};
+//For running the cpu on other machines, the emulator itself doesn't need them from here
+#[allow(unused_imports)]
+pub use self::bus::{Bus, BusCycle, FlatBus};
+pub use self::trace::*;
+pub use self::cart::CartError;
 use minifb::{WindowOptions, Window, Key, Scale};
 
 pub struct NES {
     cpu: NESCpu,
     interconnect: Interconnect,
     window: Window,
+
+    history: Option<RingBufferTrace>, //the last few instructions, printed if the cpu stops
 }
 
 impl NES {
-    pub fn new() -> NES {
+    pub fn with_cpu_mode(mode: ExecutionMode) -> NES {
         NES {
-            cpu: NESCpu::new(),
+            cpu: NESCpu::with_mode(mode),
             interconnect: Interconnect::new(),
             window: Window::new("NES Emulator", ppu::WIDTH, ppu::HEIGHT, WindowOptions {
                 borderless: false,
@@ -28,14 +45,19 @@ impl NES {
                 resize: false,
                 scale: Scale::X4,
             }).expect("Failed to create window"),
+
+            history: None,
         }
     }
 
     //Power up info obtained from NESDev wiki
     //https://wiki.nesdev.com/w/index.php/CPU_power_up_state 
     pub fn hard_restart(&mut self) {
-        self.cpu.set_p_u8(0x34);
-        self.cpu.set_s(0xFD);
+        //Like PLP and RTI, the break bit can't really be set
+        self.cpu.set_p_u8(0x34 & !CPUStatus::BREAK);
+
+        //Reset moves the stack pointer down by 3, which leaves it at $FD
+        self.cpu.set_s(0x00);
 
         self.cpu.set_a(0);
         self.cpu.set_x(0);
@@ -48,37 +70,58 @@ impl NES {
         for i in 0..0xF {
             self.interconnect.write_mem(0x4000 + i, 0);
         }
+
+        self.cpu.reset(&mut self.interconnect);
     }
 
     pub fn soft_restart(&mut self) {
-        self.cpu.offset_s(0xFD); //decrement by 3
-
-        let p = self.cpu.p();
-        self.cpu.set_p(p | 0x04);
+        self.cpu.reset(&mut self.interconnect);
 
         self.interconnect.write_mem(0x4015, 0);
     }
 
-    pub fn insert_cart(&mut self, rom: &str) {
-        self.interconnect.insert_cart(rom);
+    pub fn set_trace_sink(&mut self, sink: Option<Box<dyn TraceSink>>) {
+        self.cpu.set_trace_sink(sink);
+        self.history = None;
     }
 
-    pub fn run(&mut self) {
-        let addr_lo = self.interconnect.read_absolute(0xFFFE) as u16;
-        let addr_hi = self.interconnect.read_absolute(0xFFFF) as u16;
+    //Keeps the last count instructions to print if the cpu stops on an error.
+    //The cpu only has one trace sink, so this replaces any other
+    pub fn keep_history(&mut self, count: usize) {
+        let history = RingBufferTrace::new(count);
+
+        self.cpu.set_trace_sink(Some(Box::new(history.clone())));
+        self.history = Some(history);
+    }
 
-        self.cpu.set_pc(((addr_hi << 8) | addr_lo)-0);
+    pub fn insert_cart(&mut self, rom: &str) -> Result<(), CartError> {
+        self.interconnect.insert_cart(rom)
+    }
 
+    pub fn run(&mut self) {
         while self.window.is_open() && !self.window.is_key_down(Key::Escape) {
-            if self.cpu.do_instruction(&mut self.interconnect) == false {
-                break;
-                self.cpu.offset_pc(1);
+            match self.cpu.do_instruction(&mut self.interconnect) {
+                Ok(_) => {}
+
+                //Nothing to debug with yet, so just note it and carry on
+                Err(err @ CpuError::Breakpoint { .. }) => {
+                    println!("{}", err);
+                }
+
+                Err(err) => {
+                    if let Some(ref history) = self.history {
+                        for record in history.records() {
+                            println!("{}", record);
+                        }
+                    }
+
+                    println!("stopping: {}", err);
+                    break;
+                }
             }
 
-            self.interconnect.update(&mut self.window);
-
-            if self.interconnect.ppu().cycles() == 241 && self.interconnect.ppu().ctrl()&0x80 > 0 {
-                self.cpu.do_nmi(&mut self.interconnect);
+            if self.interconnect.poll_frame() {
+                let _ = self.window.update_with_buffer(self.interconnect.ppu().frame());
             }
         }
     }
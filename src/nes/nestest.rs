@@ -0,0 +1,230 @@
+use std::fs::File;
+use std::io::{Read, Write, BufWriter};
+
+use super::cpu::*;
+use super::interconnect::Interconnect;
+use super::bus::Bus;
+use super::opcode::*;
+
+//How many lines to show before the first line that doesn't match
+const CONTEXT_LINES: usize = 5;
+
+//nestest never runs this many instructions in automation mode,
+//so without a golden log this stops a runaway test
+const MAX_INSTRUCTIONS: usize = 10000;
+
+//Runs nestest.nes in automation mode (starting at $C000), writing a log in the
+//same format as the well known nestest.log and optionally checking it line by line
+//against one. Returns true if it matched (or there was nothing to match) and
+//nestest itself didn't report any failures
+//http://www.qmtpro.com/~nes/misc/nestest.txt
+pub fn run(rom: &str, golden: Option<&str>, log: Option<&str>) -> bool {
+    let golden_lines = match golden {
+        Some(path) => {
+            let mut text = String::new();
+
+            match File::open(path).and_then(|mut file| file.read_to_string(&mut text)) {
+                Ok(_) => {}
+                Err(why) => {
+                    println!("couldn't read golden log {}: {}", path, why);
+                    return false;
+                }
+            }
+
+            Some(text.lines().map(|line| line.trim_end().to_string()).collect::<Vec<String>>())
+        }
+
+        None => None,
+    };
+
+    let mut log = match log {
+        Some(path) => match File::create(path) {
+            Ok(file) => Some(BufWriter::new(file)),
+            Err(why) => {
+                println!("couldn't create log {}: {}", path, why);
+                return false;
+            }
+        },
+
+        None => None,
+    };
+
+    let mut interconnect = Interconnect::new();
+    if let Err(why) = interconnect.insert_cart(rom) {
+        println!("couldn't load {}: {}", rom, why);
+        return false;
+    }
+
+    //Same power up as the NES, but then jump straight to the automated tests
+    let mut cpu = NESCpu::new();
+    cpu.set_p_u8(0x24);
+    cpu.set_s(0x00);
+    cpu.reset(&mut interconnect);
+    cpu.set_pc(0xC000);
+
+    let limit = match golden_lines {
+        Some(ref lines) => lines.len(),
+        None => MAX_INSTRUCTIONS,
+    };
+
+    let mut history: Vec<String> = Vec::new();
+
+    for i in 0..limit {
+        let line = format_line(&cpu, &interconnect);
+
+        if let Some(ref mut log) = log {
+            let _ = writeln!(log, "{}", line);
+        }
+
+        if let Some(ref lines) = golden_lines {
+            if line != lines[i] {
+                println!("mismatch at line {}:", i + 1);
+
+                let start = history.len().saturating_sub(CONTEXT_LINES);
+                for previous in &history[start..] {
+                    println!("           {}", previous);
+                }
+
+                println!("expected:  {}", lines[i]);
+                println!("got:       {}", line);
+
+                return false;
+            }
+        }
+
+        history.push(line);
+
+        //The tests finish with an RTS out of $C000, back to whatever was on the stack
+        if cpu.pc() < 0x0100 && cpu.pc() > 0 {
+            break;
+        }
+
+        if let Err(err) = cpu.do_instruction(&mut interconnect) {
+            println!("stopped after {} instructions: {}", i + 1, err);
+            break;
+        }
+    }
+
+    //Error codes for the official and unofficial opcode tests, 0 means everything passed
+    let official = interconnect.peek(0x02).unwrap_or(0xFF);
+    let unofficial = interconnect.peek(0x03).unwrap_or(0xFF);
+
+    println!("nestest results: official 0x{:02X}, unofficial 0x{:02X}", official, unofficial);
+
+    official == 0 && unofficial == 0
+}
+
+//One line of nestest.log, for the instruction the cpu is about to run:
+//C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+//Anything on the bus that can't be peeked shows up as 00
+pub fn format_line<B: Bus>(cpu: &NESCpu, bus: &B) -> String {
+    let pc = cpu.pc();
+
+    let op = bus.peek(pc).unwrap_or(0) as u32;
+    let imm1 = bus.peek(pc.wrapping_add(1)).unwrap_or(0) as u32;
+    let imm2 = bus.peek(pc.wrapping_add(2)).unwrap_or(0) as u32;
+    let opcode = Opcode::new(op | (imm1 << 8) | (imm2 << 16));
+    let info = opcode.info();
+
+    let bytes: Vec<String> = [opcode.op(), opcode.imm1(), opcode.imm2()][..info.len as usize]
+        .iter().map(|b| format!("{:02X}", b)).collect();
+
+    //Unofficial opcodes are marked with a * in place of the space
+    let marker = if info.official { " " } else { "*" };
+
+    let ppu = bus.ppu_position();
+
+    format!("{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+            pc, bytes.join(" "), marker, disassemble(cpu, bus, &opcode),
+            cpu.a(), cpu.x(), cpu.y(), cpu.p().to_u8(), cpu.s(),
+            ppu.0, ppu.1, cpu.cycles())
+}
+
+//Disassembles the instruction at pc, annotated with the addresses
+//and values it's going to touch the way nestest.log does
+fn disassemble<B: Bus>(cpu: &NESCpu, bus: &B, opcode: &Opcode) -> String {
+    let info = opcode.info();
+    let pc = cpu.pc();
+
+    //nestest.log has its own names for a few of these
+    let mnemonic = match info.mnemonic {
+        "ISC" => "ISB",
+        mnemonic => mnemonic,
+    };
+
+    let peek = |addr: u16| bus.peek(addr).unwrap_or(0);
+    let peek_word = |lo: u16, hi: u16| (peek(lo) as u16) | ((peek(hi) as u16) << 8);
+
+    let imm1 = opcode.imm1();
+    let abs = opcode.abs_addr() as u16;
+
+    //Jumps don't read their operand, so they don't show the value there
+    let is_jump = mnemonic == "JMP" || mnemonic == "JSR";
+
+    let operand = match info.mode {
+        AddressingMode::Implied => String::new(),
+
+        AddressingMode::Accumulator => "A".to_string(),
+
+        AddressingMode::Immediate => format!("#${:02X}", imm1),
+
+        AddressingMode::ZeroPage => format!("${:02X} = {:02X}", imm1, peek(imm1 as u16)),
+
+        AddressingMode::ZeroPageX => {
+            let addr = imm1.wrapping_add(cpu.x());
+            format!("${:02X},X @ {:02X} = {:02X}", imm1, addr, peek(addr as u16))
+        }
+
+        AddressingMode::ZeroPageY => {
+            let addr = imm1.wrapping_add(cpu.y());
+            format!("${:02X},Y @ {:02X} = {:02X}", imm1, addr, peek(addr as u16))
+        }
+
+        AddressingMode::Absolute => {
+            if is_jump {
+                format!("${:04X}", abs)
+            } else {
+                format!("${:04X} = {:02X}", abs, peek(abs))
+            }
+        }
+
+        AddressingMode::AbsoluteX => {
+            let addr = abs.wrapping_add(cpu.x() as u16);
+            format!("${:04X},X @ {:04X} = {:02X}", abs, addr, peek(addr))
+        }
+
+        AddressingMode::AbsoluteY => {
+            let addr = abs.wrapping_add(cpu.y() as u16);
+            format!("${:04X},Y @ {:04X} = {:02X}", abs, addr, peek(addr))
+        }
+
+        //The pointer's high byte comes from the same page, like the real JMP ($xxFF)
+        AddressingMode::Indirect => {
+            let hi = (abs&0xFF00) | (abs.wrapping_add(1)&0x00FF);
+            format!("(${:04X}) = {:04X}", abs, peek_word(abs, hi))
+        }
+
+        AddressingMode::IndirectX => {
+            let pointer = imm1.wrapping_add(cpu.x());
+            let addr = peek_word(pointer as u16, pointer.wrapping_add(1) as u16);
+            format!("(${:02X},X) @ {:02X} = {:04X} = {:02X}", imm1, pointer, addr, peek(addr))
+        }
+
+        AddressingMode::IndirectY => {
+            let base = peek_word(imm1 as u16, imm1.wrapping_add(1) as u16);
+            let addr = base.wrapping_add(cpu.y() as u16);
+            format!("(${:02X}),Y = {:04X} @ {:04X} = {:02X}", imm1, base, addr, peek(addr))
+        }
+
+        AddressingMode::Relative => {
+            let target = pc.wrapping_add(2).wrapping_add(imm1 as i8 as u16);
+            format!("${:04X}", target)
+        }
+    };
+
+    if operand.is_empty() {
+        mnemonic.to_string()
+    } else {
+        format!("{} {}", mnemonic, operand)
+    }
+}
@@ -1,7 +1,9 @@
 use std::fmt;
 
+use self::AddressingMode::*;
+
 enum_from_primitive! {
-    #[derive(Debug, PartialEq)]
+    #[derive(Copy, Clone, Debug, PartialEq)]
     pub enum Op {
         BRKImmediate   = 0x00,
         ORAIndirectX   = 0x01,
@@ -21,6 +23,7 @@ enum_from_primitive! {
         ORAAbsoluteX   = 0x1D,
         ASLAbsoluteX   = 0x1E,
         JSRAbsolute    = 0x20,
+        ANDIndirectX   = 0x21,
         BITZeroPage    = 0x24,
         ANDZeroPage    = 0x25,
         ROLZeroPage    = 0x26,
@@ -39,8 +42,9 @@ enum_from_primitive! {
         ANDAbsoluteX   = 0x3D,
         ROLAbsoluteX   = 0x3E,
         RTIImplied     = 0x40,
-        LSRZeroPage    = 0x46,
+        EORIndirectX   = 0x41,
         EORZeroPage    = 0x45,
+        LSRZeroPage    = 0x46,
         PHAImplied     = 0x48,
         EORImmediate   = 0x49,
         LSRAccumulator = 0x4A,
@@ -49,9 +53,12 @@ enum_from_primitive! {
         LSRAbsolute    = 0x4E,
         BVCRelative    = 0x50,
         EORIndirectY   = 0x51,
+        EORZeroPageX   = 0x55,
         LSRZeroPageX   = 0x56,
         CLIImplied     = 0x58,
         EORAbsoluteY   = 0x59,
+        EORAbsoluteX   = 0x5D,
+        LSRAbsoluteX   = 0x5E,
         RTSImplied     = 0x60,
         ADCIndirectX   = 0x61,
         ADCZeroPage    = 0x65,
@@ -69,6 +76,7 @@ enum_from_primitive! {
         SEIImplied     = 0x78,
         ADCAbsoluteY   = 0x79,
         ADCAbsoluteX   = 0x7D,
+        RORAbsoluteX   = 0x7E,
         STAIndirectX   = 0x81,
         STYZeroPage    = 0x84,
         STAZeroPage    = 0x85,
@@ -76,8 +84,8 @@ enum_from_primitive! {
         DEYImplied     = 0x88,
         TXAImplied     = 0x8A,
         STYAbsolute    = 0x8C,
-        STXAbsolute    = 0x8E,
         STAAbsolute    = 0x8D,
+        STXAbsolute    = 0x8E,
         BCCRelative    = 0x90,
         STAIndirectY   = 0x91,
         STYZeroPageX   = 0x94,
@@ -96,20 +104,22 @@ enum_from_primitive! {
         TAYImplied     = 0xA8,
         LDAImmediate   = 0xA9,
         TAXImplied     = 0xAA,
-        LDAAbsolute    = 0xAD,
         LDYAbsolute    = 0xAC,
+        LDAAbsolute    = 0xAD,
         LDXAbsolute    = 0xAE,
         BCSRelative    = 0xB0,
         LDAIndirectY   = 0xB1,
         LDYZeroPageX   = 0xB4,
         LDAZeroPageX   = 0xB5,
         LDXZeroPageY   = 0xB6,
+        CLVImplied     = 0xB8,
         LDAAbsoluteY   = 0xB9,
         TSXImplied     = 0xBA,
         LDYAbsoluteX   = 0xBC,
         LDAAbsoluteX   = 0xBD,
         LDXAbsoluteY   = 0xBE,
         CPYImmediate   = 0xC0,
+        CMPIndirectX   = 0xC1,
         CPYZeroPage    = 0xC4,
         CMPZeroPage    = 0xC5,
         DECZeroPage    = 0xC6,
@@ -128,14 +138,16 @@ enum_from_primitive! {
         CMPAbsoluteX   = 0xDD,
         DECAbsoluteX   = 0xDE,
         CPXImmediate   = 0xE0,
+        SBCIndirectX   = 0xE1,
         CPXZeroPage    = 0xE4,
         SBCZeroPage    = 0xE5,
         INCZeroPage    = 0xE6,
         INXImplied     = 0xE8,
         SBCImmediate   = 0xE9,
+        NOPImplied     = 0xEA,
         CPXAbsolute    = 0xEC,
-        INCAbsolute    = 0xEE,
         SBCAbsolute    = 0xED,
+        INCAbsolute    = 0xEE,
         BEQRelative    = 0xF0,
         SBCIndirectY   = 0xF1,
         SBCZeroPageX   = 0xF5,
@@ -147,7 +159,633 @@ enum_from_primitive! {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+impl AddressingMode {
+    //Number of bytes the instruction takes up, including the opcode
+    pub const fn len(&self) -> u8 {
+        match *self {
+            Implied | Accumulator => 1,
+            Immediate | ZeroPage | ZeroPageX | ZeroPageY |
+            IndirectX | IndirectY | Relative => 2,
+            Absolute | AbsoluteX | AbsoluteY | Indirect => 3,
+        }
+    }
+}
+
+//Everything we know about an opcode byte without executing it
+#[derive(Copy, Clone, Debug)]
+pub struct OpInfo {
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    pub len: u8,
+
+    //Cycles taken when no page is crossed and no branch is taken
+    pub cycles: u8,
+
+    //Indexed reads take one extra cycle when the index crosses a page,
+    //branches are not marked here since they have their own rules
+    pub page_penalty: bool,
+
+    pub official: bool,
+}
+
+const fn op(mnemonic: &'static str, mode: AddressingMode, cycles: u8, page_penalty: bool, official: bool) -> OpInfo {
+    OpInfo {
+        mnemonic: mnemonic,
+        mode: mode,
+        len: mode.len(),
+        cycles: cycles,
+        page_penalty: page_penalty,
+        official: official,
+    }
+}
+
+//Op for every opcode byte, so decoding is a single lookup
+//(unofficial opcodes are None, they're looked up in OP_TABLE instead)
+pub static DECODE_TABLE: [Option<Op>; 256] = [
+    //0x00 - 0x0F
+    Some(Op::BRKImmediate),
+    Some(Op::ORAIndirectX),
+    None,
+    None,
+    None,
+    Some(Op::ORAZeroPage),
+    Some(Op::ASLZeroPage),
+    None,
+    Some(Op::PHPImplied),
+    Some(Op::ORAImmediate),
+    Some(Op::ASLAccumulator),
+    None,
+    None,
+    Some(Op::ORAAbsolute),
+    Some(Op::ASLAbsolute),
+    None,
+    //0x10 - 0x1F
+    Some(Op::BPLRelative),
+    Some(Op::ORAIndirectY),
+    None,
+    None,
+    None,
+    Some(Op::ORAZeroPageX),
+    Some(Op::ASLZeroPageX),
+    None,
+    Some(Op::CLCImplied),
+    Some(Op::ORAAbsoluteY),
+    None,
+    None,
+    None,
+    Some(Op::ORAAbsoluteX),
+    Some(Op::ASLAbsoluteX),
+    None,
+    //0x20 - 0x2F
+    Some(Op::JSRAbsolute),
+    Some(Op::ANDIndirectX),
+    None,
+    None,
+    Some(Op::BITZeroPage),
+    Some(Op::ANDZeroPage),
+    Some(Op::ROLZeroPage),
+    None,
+    Some(Op::PLPImplied),
+    Some(Op::ANDImmediate),
+    Some(Op::ROLAccumulator),
+    None,
+    Some(Op::BITAbsolute),
+    Some(Op::ANDAbsolute),
+    Some(Op::ROLAbsolute),
+    None,
+    //0x30 - 0x3F
+    Some(Op::BMIRelative),
+    Some(Op::ANDIndirectY),
+    None,
+    None,
+    None,
+    Some(Op::ANDZeroPageX),
+    Some(Op::ROLZeroPageX),
+    None,
+    Some(Op::SECImplied),
+    Some(Op::ANDAbsoluteY),
+    None,
+    None,
+    None,
+    Some(Op::ANDAbsoluteX),
+    Some(Op::ROLAbsoluteX),
+    None,
+    //0x40 - 0x4F
+    Some(Op::RTIImplied),
+    Some(Op::EORIndirectX),
+    None,
+    None,
+    None,
+    Some(Op::EORZeroPage),
+    Some(Op::LSRZeroPage),
+    None,
+    Some(Op::PHAImplied),
+    Some(Op::EORImmediate),
+    Some(Op::LSRAccumulator),
+    None,
+    Some(Op::JMPAbsolute),
+    Some(Op::EORAbsolute),
+    Some(Op::LSRAbsolute),
+    None,
+    //0x50 - 0x5F
+    Some(Op::BVCRelative),
+    Some(Op::EORIndirectY),
+    None,
+    None,
+    None,
+    Some(Op::EORZeroPageX),
+    Some(Op::LSRZeroPageX),
+    None,
+    Some(Op::CLIImplied),
+    Some(Op::EORAbsoluteY),
+    None,
+    None,
+    None,
+    Some(Op::EORAbsoluteX),
+    Some(Op::LSRAbsoluteX),
+    None,
+    //0x60 - 0x6F
+    Some(Op::RTSImplied),
+    Some(Op::ADCIndirectX),
+    None,
+    None,
+    None,
+    Some(Op::ADCZeroPage),
+    Some(Op::RORZeroPage),
+    None,
+    Some(Op::PLAImplied),
+    Some(Op::ADCImmediate),
+    Some(Op::RORAccumulator),
+    None,
+    Some(Op::JMPAbsIndirect),
+    Some(Op::ADCAbsolute),
+    Some(Op::RORAbsolute),
+    None,
+    //0x70 - 0x7F
+    Some(Op::BVSRelative),
+    Some(Op::ADCIndirectY),
+    None,
+    None,
+    None,
+    Some(Op::ADCZeroPageX),
+    Some(Op::RORZeroPageX),
+    None,
+    Some(Op::SEIImplied),
+    Some(Op::ADCAbsoluteY),
+    None,
+    None,
+    None,
+    Some(Op::ADCAbsoluteX),
+    Some(Op::RORAbsoluteX),
+    None,
+    //0x80 - 0x8F
+    None,
+    Some(Op::STAIndirectX),
+    None,
+    None,
+    Some(Op::STYZeroPage),
+    Some(Op::STAZeroPage),
+    Some(Op::STXZeroPage),
+    None,
+    Some(Op::DEYImplied),
+    None,
+    Some(Op::TXAImplied),
+    None,
+    Some(Op::STYAbsolute),
+    Some(Op::STAAbsolute),
+    Some(Op::STXAbsolute),
+    None,
+    //0x90 - 0x9F
+    Some(Op::BCCRelative),
+    Some(Op::STAIndirectY),
+    None,
+    None,
+    Some(Op::STYZeroPageX),
+    Some(Op::STAZeroPageX),
+    Some(Op::STXZeroPageY),
+    None,
+    Some(Op::TYAImplied),
+    Some(Op::STAAbsoluteY),
+    Some(Op::TXSImplied),
+    None,
+    None,
+    Some(Op::STAAbsoluteX),
+    None,
+    None,
+    //0xA0 - 0xAF
+    Some(Op::LDYImmediate),
+    Some(Op::LDAIndirectX),
+    Some(Op::LDXImmediate),
+    None,
+    Some(Op::LDYZeroPage),
+    Some(Op::LDAZeroPage),
+    Some(Op::LDXZeroPage),
+    None,
+    Some(Op::TAYImplied),
+    Some(Op::LDAImmediate),
+    Some(Op::TAXImplied),
+    None,
+    Some(Op::LDYAbsolute),
+    Some(Op::LDAAbsolute),
+    Some(Op::LDXAbsolute),
+    None,
+    //0xB0 - 0xBF
+    Some(Op::BCSRelative),
+    Some(Op::LDAIndirectY),
+    None,
+    None,
+    Some(Op::LDYZeroPageX),
+    Some(Op::LDAZeroPageX),
+    Some(Op::LDXZeroPageY),
+    None,
+    Some(Op::CLVImplied),
+    Some(Op::LDAAbsoluteY),
+    Some(Op::TSXImplied),
+    None,
+    Some(Op::LDYAbsoluteX),
+    Some(Op::LDAAbsoluteX),
+    Some(Op::LDXAbsoluteY),
+    None,
+    //0xC0 - 0xCF
+    Some(Op::CPYImmediate),
+    Some(Op::CMPIndirectX),
+    None,
+    None,
+    Some(Op::CPYZeroPage),
+    Some(Op::CMPZeroPage),
+    Some(Op::DECZeroPage),
+    None,
+    Some(Op::INYImplied),
+    Some(Op::CMPImmediate),
+    Some(Op::DEXImplied),
+    None,
+    Some(Op::CPYAbsolute),
+    Some(Op::CMPAbsolute),
+    Some(Op::DECAbsolute),
+    None,
+    //0xD0 - 0xDF
+    Some(Op::BNERelative),
+    Some(Op::CMPIndirectY),
+    None,
+    None,
+    None,
+    Some(Op::CMPZeroPageX),
+    Some(Op::DECZeroPageX),
+    None,
+    Some(Op::CLDImplied),
+    Some(Op::CMPAbsoluteY),
+    None,
+    None,
+    None,
+    Some(Op::CMPAbsoluteX),
+    Some(Op::DECAbsoluteX),
+    None,
+    //0xE0 - 0xEF
+    Some(Op::CPXImmediate),
+    Some(Op::SBCIndirectX),
+    None,
+    None,
+    Some(Op::CPXZeroPage),
+    Some(Op::SBCZeroPage),
+    Some(Op::INCZeroPage),
+    None,
+    Some(Op::INXImplied),
+    Some(Op::SBCImmediate),
+    Some(Op::NOPImplied),
+    None,
+    Some(Op::CPXAbsolute),
+    Some(Op::SBCAbsolute),
+    Some(Op::INCAbsolute),
+    None,
+    //0xF0 - 0xFF
+    Some(Op::BEQRelative),
+    Some(Op::SBCIndirectY),
+    None,
+    None,
+    None,
+    Some(Op::SBCZeroPageX),
+    Some(Op::INCZeroPageX),
+    None,
+    Some(Op::SEDImplied),
+    Some(Op::SBCAbsoluteY),
+    None,
+    None,
+    None,
+    Some(Op::SBCAbsoluteX),
+    Some(Op::INCAbsoluteX),
+    None,
+];
+
+//Opcode matrix obtained from NESDev wiki
+//https://wiki.nesdev.com/w/index.php/CPU_unofficial_opcodes
+pub static OP_TABLE: [OpInfo; 256] = [
+    //0x00 - 0x0F
+    op("BRK", Implied,     7, false, true),
+    op("ORA", IndirectX,   6, false, true),
+    op("JAM", Implied,     2, false, false),
+    op("SLO", IndirectX,   8, false, false),
+    op("NOP", ZeroPage,    3, false, false),
+    op("ORA", ZeroPage,    3, false, true),
+    op("ASL", ZeroPage,    5, false, true),
+    op("SLO", ZeroPage,    5, false, false),
+    op("PHP", Implied,     3, false, true),
+    op("ORA", Immediate,   2, false, true),
+    op("ASL", Accumulator, 2, false, true),
+    op("ANC", Immediate,   2, false, false),
+    op("NOP", Absolute,    4, false, false),
+    op("ORA", Absolute,    4, false, true),
+    op("ASL", Absolute,    6, false, true),
+    op("SLO", Absolute,    6, false, false),
+
+    //0x10 - 0x1F
+    op("BPL", Relative,    2, false, true),
+    op("ORA", IndirectY,   5, true,  true),
+    op("JAM", Implied,     2, false, false),
+    op("SLO", IndirectY,   8, false, false),
+    op("NOP", ZeroPageX,   4, false, false),
+    op("ORA", ZeroPageX,   4, false, true),
+    op("ASL", ZeroPageX,   6, false, true),
+    op("SLO", ZeroPageX,   6, false, false),
+    op("CLC", Implied,     2, false, true),
+    op("ORA", AbsoluteY,   4, true,  true),
+    op("NOP", Implied,     2, false, false),
+    op("SLO", AbsoluteY,   7, false, false),
+    op("NOP", AbsoluteX,   4, true,  false),
+    op("ORA", AbsoluteX,   4, true,  true),
+    op("ASL", AbsoluteX,   7, false, true),
+    op("SLO", AbsoluteX,   7, false, false),
+
+    //0x20 - 0x2F
+    op("JSR", Absolute,    6, false, true),
+    op("AND", IndirectX,   6, false, true),
+    op("JAM", Implied,     2, false, false),
+    op("RLA", IndirectX,   8, false, false),
+    op("BIT", ZeroPage,    3, false, true),
+    op("AND", ZeroPage,    3, false, true),
+    op("ROL", ZeroPage,    5, false, true),
+    op("RLA", ZeroPage,    5, false, false),
+    op("PLP", Implied,     4, false, true),
+    op("AND", Immediate,   2, false, true),
+    op("ROL", Accumulator, 2, false, true),
+    op("ANC", Immediate,   2, false, false),
+    op("BIT", Absolute,    4, false, true),
+    op("AND", Absolute,    4, false, true),
+    op("ROL", Absolute,    6, false, true),
+    op("RLA", Absolute,    6, false, false),
+
+    //0x30 - 0x3F
+    op("BMI", Relative,    2, false, true),
+    op("AND", IndirectY,   5, true,  true),
+    op("JAM", Implied,     2, false, false),
+    op("RLA", IndirectY,   8, false, false),
+    op("NOP", ZeroPageX,   4, false, false),
+    op("AND", ZeroPageX,   4, false, true),
+    op("ROL", ZeroPageX,   6, false, true),
+    op("RLA", ZeroPageX,   6, false, false),
+    op("SEC", Implied,     2, false, true),
+    op("AND", AbsoluteY,   4, true,  true),
+    op("NOP", Implied,     2, false, false),
+    op("RLA", AbsoluteY,   7, false, false),
+    op("NOP", AbsoluteX,   4, true,  false),
+    op("AND", AbsoluteX,   4, true,  true),
+    op("ROL", AbsoluteX,   7, false, true),
+    op("RLA", AbsoluteX,   7, false, false),
+
+    //0x40 - 0x4F
+    op("RTI", Implied,     6, false, true),
+    op("EOR", IndirectX,   6, false, true),
+    op("JAM", Implied,     2, false, false),
+    op("SRE", IndirectX,   8, false, false),
+    op("NOP", ZeroPage,    3, false, false),
+    op("EOR", ZeroPage,    3, false, true),
+    op("LSR", ZeroPage,    5, false, true),
+    op("SRE", ZeroPage,    5, false, false),
+    op("PHA", Implied,     3, false, true),
+    op("EOR", Immediate,   2, false, true),
+    op("LSR", Accumulator, 2, false, true),
+    op("ALR", Immediate,   2, false, false),
+    op("JMP", Absolute,    3, false, true),
+    op("EOR", Absolute,    4, false, true),
+    op("LSR", Absolute,    6, false, true),
+    op("SRE", Absolute,    6, false, false),
+
+    //0x50 - 0x5F
+    op("BVC", Relative,    2, false, true),
+    op("EOR", IndirectY,   5, true,  true),
+    op("JAM", Implied,     2, false, false),
+    op("SRE", IndirectY,   8, false, false),
+    op("NOP", ZeroPageX,   4, false, false),
+    op("EOR", ZeroPageX,   4, false, true),
+    op("LSR", ZeroPageX,   6, false, true),
+    op("SRE", ZeroPageX,   6, false, false),
+    op("CLI", Implied,     2, false, true),
+    op("EOR", AbsoluteY,   4, true,  true),
+    op("NOP", Implied,     2, false, false),
+    op("SRE", AbsoluteY,   7, false, false),
+    op("NOP", AbsoluteX,   4, true,  false),
+    op("EOR", AbsoluteX,   4, true,  true),
+    op("LSR", AbsoluteX,   7, false, true),
+    op("SRE", AbsoluteX,   7, false, false),
+
+    //0x60 - 0x6F
+    op("RTS", Implied,     6, false, true),
+    op("ADC", IndirectX,   6, false, true),
+    op("JAM", Implied,     2, false, false),
+    op("RRA", IndirectX,   8, false, false),
+    op("NOP", ZeroPage,    3, false, false),
+    op("ADC", ZeroPage,    3, false, true),
+    op("ROR", ZeroPage,    5, false, true),
+    op("RRA", ZeroPage,    5, false, false),
+    op("PLA", Implied,     4, false, true),
+    op("ADC", Immediate,   2, false, true),
+    op("ROR", Accumulator, 2, false, true),
+    op("ARR", Immediate,   2, false, false),
+    op("JMP", Indirect,    5, false, true),
+    op("ADC", Absolute,    4, false, true),
+    op("ROR", Absolute,    6, false, true),
+    op("RRA", Absolute,    6, false, false),
+
+    //0x70 - 0x7F
+    op("BVS", Relative,    2, false, true),
+    op("ADC", IndirectY,   5, true,  true),
+    op("JAM", Implied,     2, false, false),
+    op("RRA", IndirectY,   8, false, false),
+    op("NOP", ZeroPageX,   4, false, false),
+    op("ADC", ZeroPageX,   4, false, true),
+    op("ROR", ZeroPageX,   6, false, true),
+    op("RRA", ZeroPageX,   6, false, false),
+    op("SEI", Implied,     2, false, true),
+    op("ADC", AbsoluteY,   4, true,  true),
+    op("NOP", Implied,     2, false, false),
+    op("RRA", AbsoluteY,   7, false, false),
+    op("NOP", AbsoluteX,   4, true,  false),
+    op("ADC", AbsoluteX,   4, true,  true),
+    op("ROR", AbsoluteX,   7, false, true),
+    op("RRA", AbsoluteX,   7, false, false),
+
+    //0x80 - 0x8F
+    op("NOP", Immediate,   2, false, false),
+    op("STA", IndirectX,   6, false, true),
+    op("NOP", Immediate,   2, false, false),
+    op("SAX", IndirectX,   6, false, false),
+    op("STY", ZeroPage,    3, false, true),
+    op("STA", ZeroPage,    3, false, true),
+    op("STX", ZeroPage,    3, false, true),
+    op("SAX", ZeroPage,    3, false, false),
+    op("DEY", Implied,     2, false, true),
+    op("NOP", Immediate,   2, false, false),
+    op("TXA", Implied,     2, false, true),
+    op("XAA", Immediate,   2, false, false),
+    op("STY", Absolute,    4, false, true),
+    op("STA", Absolute,    4, false, true),
+    op("STX", Absolute,    4, false, true),
+    op("SAX", Absolute,    4, false, false),
+
+    //0x90 - 0x9F
+    op("BCC", Relative,    2, false, true),
+    op("STA", IndirectY,   6, false, true),
+    op("JAM", Implied,     2, false, false),
+    op("AHX", IndirectY,   6, false, false),
+    op("STY", ZeroPageX,   4, false, true),
+    op("STA", ZeroPageX,   4, false, true),
+    op("STX", ZeroPageY,   4, false, true),
+    op("SAX", ZeroPageY,   4, false, false),
+    op("TYA", Implied,     2, false, true),
+    op("STA", AbsoluteY,   5, false, true),
+    op("TXS", Implied,     2, false, true),
+    op("TAS", AbsoluteY,   5, false, false),
+    op("SHY", AbsoluteX,   5, false, false),
+    op("STA", AbsoluteX,   5, false, true),
+    op("SHX", AbsoluteY,   5, false, false),
+    op("AHX", AbsoluteY,   5, false, false),
+
+    //0xA0 - 0xAF
+    op("LDY", Immediate,   2, false, true),
+    op("LDA", IndirectX,   6, false, true),
+    op("LDX", Immediate,   2, false, true),
+    op("LAX", IndirectX,   6, false, false),
+    op("LDY", ZeroPage,    3, false, true),
+    op("LDA", ZeroPage,    3, false, true),
+    op("LDX", ZeroPage,    3, false, true),
+    op("LAX", ZeroPage,    3, false, false),
+    op("TAY", Implied,     2, false, true),
+    op("LDA", Immediate,   2, false, true),
+    op("TAX", Implied,     2, false, true),
+    op("LXA", Immediate,   2, false, false),
+    op("LDY", Absolute,    4, false, true),
+    op("LDA", Absolute,    4, false, true),
+    op("LDX", Absolute,    4, false, true),
+    op("LAX", Absolute,    4, false, false),
+
+    //0xB0 - 0xBF
+    op("BCS", Relative,    2, false, true),
+    op("LDA", IndirectY,   5, true,  true),
+    op("JAM", Implied,     2, false, false),
+    op("LAX", IndirectY,   5, true,  false),
+    op("LDY", ZeroPageX,   4, false, true),
+    op("LDA", ZeroPageX,   4, false, true),
+    op("LDX", ZeroPageY,   4, false, true),
+    op("LAX", ZeroPageY,   4, false, false),
+    op("CLV", Implied,     2, false, true),
+    op("LDA", AbsoluteY,   4, true,  true),
+    op("TSX", Implied,     2, false, true),
+    op("LAS", AbsoluteY,   4, true,  false),
+    op("LDY", AbsoluteX,   4, true,  true),
+    op("LDA", AbsoluteX,   4, true,  true),
+    op("LDX", AbsoluteY,   4, true,  true),
+    op("LAX", AbsoluteY,   4, true,  false),
+
+    //0xC0 - 0xCF
+    op("CPY", Immediate,   2, false, true),
+    op("CMP", IndirectX,   6, false, true),
+    op("NOP", Immediate,   2, false, false),
+    op("DCP", IndirectX,   8, false, false),
+    op("CPY", ZeroPage,    3, false, true),
+    op("CMP", ZeroPage,    3, false, true),
+    op("DEC", ZeroPage,    5, false, true),
+    op("DCP", ZeroPage,    5, false, false),
+    op("INY", Implied,     2, false, true),
+    op("CMP", Immediate,   2, false, true),
+    op("DEX", Implied,     2, false, true),
+    op("AXS", Immediate,   2, false, false),
+    op("CPY", Absolute,    4, false, true),
+    op("CMP", Absolute,    4, false, true),
+    op("DEC", Absolute,    6, false, true),
+    op("DCP", Absolute,    6, false, false),
+
+    //0xD0 - 0xDF
+    op("BNE", Relative,    2, false, true),
+    op("CMP", IndirectY,   5, true,  true),
+    op("JAM", Implied,     2, false, false),
+    op("DCP", IndirectY,   8, false, false),
+    op("NOP", ZeroPageX,   4, false, false),
+    op("CMP", ZeroPageX,   4, false, true),
+    op("DEC", ZeroPageX,   6, false, true),
+    op("DCP", ZeroPageX,   6, false, false),
+    op("CLD", Implied,     2, false, true),
+    op("CMP", AbsoluteY,   4, true,  true),
+    op("NOP", Implied,     2, false, false),
+    op("DCP", AbsoluteY,   7, false, false),
+    op("NOP", AbsoluteX,   4, true,  false),
+    op("CMP", AbsoluteX,   4, true,  true),
+    op("DEC", AbsoluteX,   7, false, true),
+    op("DCP", AbsoluteX,   7, false, false),
+
+    //0xE0 - 0xEF
+    op("CPX", Immediate,   2, false, true),
+    op("SBC", IndirectX,   6, false, true),
+    op("NOP", Immediate,   2, false, false),
+    op("ISC", IndirectX,   8, false, false),
+    op("CPX", ZeroPage,    3, false, true),
+    op("SBC", ZeroPage,    3, false, true),
+    op("INC", ZeroPage,    5, false, true),
+    op("ISC", ZeroPage,    5, false, false),
+    op("INX", Implied,     2, false, true),
+    op("SBC", Immediate,   2, false, true),
+    op("NOP", Implied,     2, false, true),
+    op("SBC", Immediate,   2, false, false),
+    op("CPX", Absolute,    4, false, true),
+    op("SBC", Absolute,    4, false, true),
+    op("INC", Absolute,    6, false, true),
+    op("ISC", Absolute,    6, false, false),
+
+    //0xF0 - 0xFF
+    op("BEQ", Relative,    2, false, true),
+    op("SBC", IndirectY,   5, true,  true),
+    op("JAM", Implied,     2, false, false),
+    op("ISC", IndirectY,   8, false, false),
+    op("NOP", ZeroPageX,   4, false, false),
+    op("SBC", ZeroPageX,   4, false, true),
+    op("INC", ZeroPageX,   6, false, true),
+    op("ISC", ZeroPageX,   6, false, false),
+    op("SED", Implied,     2, false, true),
+    op("SBC", AbsoluteY,   4, true,  true),
+    op("NOP", Implied,     2, false, false),
+    op("ISC", AbsoluteY,   7, false, false),
+    op("NOP", AbsoluteX,   4, true,  false),
+    op("SBC", AbsoluteX,   4, true,  true),
+    op("INC", AbsoluteX,   7, false, true),
+    op("ISC", AbsoluteX,   7, false, false),
+];
+
+#[derive(Copy, Clone, PartialEq)]
 pub struct Opcode {
     instruction: u32,
 }
@@ -174,6 +812,15 @@ impl Opcode {
     pub fn abs_addr(&self) -> usize {
         (self.instruction >> 8) as usize
     }
+
+    pub fn info(&self) -> &'static OpInfo {
+        &OP_TABLE[self.op() as usize]
+    }
+
+    //None for unofficial opcodes
+    pub fn decode(&self) -> Option<Op> {
+        DECODE_TABLE[self.op() as usize]
+    }
 }
 
 impl fmt::Debug for Opcode {
@@ -182,3 +829,35 @@ impl fmt::Debug for Opcode {
                self.op(), self.imm1(), self.imm2())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use enum_primitive::FromPrimitive;
+
+    //DECODE_TABLE is written out by hand, so check it against the enum it mirrors
+    #[test]
+    fn decode_table_matches_from_i32() {
+        for op in 0..0x100 {
+            assert_eq!(DECODE_TABLE[op as usize], Op::from_i32(op), "opcode {:02X}", op);
+        }
+    }
+
+    //Op's names are the mnemonic then the addressing mode, which should agree with
+    //OP_TABLE, apart from two that were named differently before OP_TABLE existed
+    #[test]
+    fn decode_table_matches_op_table() {
+        for op in 0..0x100 {
+            if let Some(decoded) = DECODE_TABLE[op] {
+                let info = &OP_TABLE[op];
+                let name = match decoded {
+                    Op::BRKImmediate => "BRKImplied".to_string(),
+                    Op::JMPAbsIndirect => "JMPIndirect".to_string(),
+                    _ => format!("{:?}", decoded),
+                };
+
+                assert_eq!(name, format!("{}{:?}", info.mnemonic, info.mode), "opcode {:02X}", op);
+            }
+        }
+    }
+}
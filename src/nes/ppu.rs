@@ -1,5 +1,4 @@
-use minifb::{WindowOptions, Window, Key, Scale};
-use super::interconnect::Interconnect;
+use super::interconnect::VideoBus;
 
 pub const WIDTH: usize = 256;// 341;
 pub const HEIGHT: usize = 240;
@@ -12,14 +11,26 @@ pub struct NESPpu {
     oam_data: u8,
     scroll: u8,
     addr: u8,
-    data: u8,
     oam_dma: u8,
 
     oam: [u8; 0xFF],
 
+    //Set through $2006 (and $2005, they share the write toggle), then
+    //$2007 reads and writes the PPU's bus there
+    vram_addr: u16,
+    write_toggle: bool,
+    read_buffer: u8, //$2007 reads come back a read late, except for the palette
+
+    palette: [u8; 0x20],
+
     vram: Vec<u32>,
+    prefetch: [(u8, u8); 2], //pattern bytes of the next line's first two tiles
 
-    cycles: i32,
+    //Position of the beam, the pre-render scanline is -1
+    //https://wiki.nesdev.com/w/index.php/PPU_rendering
+    dot: u16,
+    scanline: i16,
+    odd_frame: bool,
 }
 
 impl NESPpu {
@@ -36,29 +47,68 @@ impl NESPpu {
             oam_data: 0,
             scroll: 0,
             addr: 0,
-            data: 0,
-            //odd_frame: false,
             oam_dma: 0,
 
             oam: [0; 0xFF],
 
+            vram_addr: 0,
+            write_toggle: false,
+            read_buffer: 0,
+
+            palette: [0; 0x20],
+
             vram: vec![0u32; WIDTH*HEIGHT],
+            prefetch: [(0, 0); 2],
+
+            dot: 0,
+            scanline: 0,
+            odd_frame: false,
+        }
+    }
+
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    pub fn scanline(&self) -> i16 {
+        self.scanline
+    }
+
+    pub fn frame(&self) -> &[u32] {
+        &self.vram
+    }
+
+    //Background or sprites are turned on
+    pub fn rendering(&self) -> bool {
+        self.mask&0x18 > 0
+    }
 
-            cycles: 0,
+    //$3F10, $3F14, $3F18 and $3F1C are the same bytes as $3F00, $3F04...
+    fn palette_index(addr: u16) -> usize {
+        let index = (addr & 0x1F) as usize;
+
+        if index >= 0x10 && index % 4 == 0 {
+            index - 0x10
+        } else {
+            index
         }
     }
 
-    pub fn ctrl(&self) -> u8 {
-        self.ctrl
+    //$2007 steps the address by 1, or by 32 (a row of tiles) when ctrl bit 2 is set
+    fn increment_vram_addr(&mut self) {
+        let step = if self.ctrl&0x4 > 0 { 32 } else { 1 };
+        self.vram_addr = self.vram_addr.wrapping_add(step) & 0x3FFF;
     }
 
-    pub fn cycles(&self) -> i32 {
-        self.cycles
+    //The PPU holds /NMI low for as long as vblank is flagged and NMIs are enabled,
+    //the cpu only reacts to it going low
+    pub fn nmi_line(&self) -> bool {
+        self.status&0x80 > 0 && self.ctrl&0x80 > 0
     }
 
     //Info on what address maps to what
     //https://wiki.nesdev.com/w/index.php/PPU_registers
-    pub fn read_ppu(&mut self, addr: usize) -> u8 {
+    pub fn read_ppu(&mut self, addr: usize, bus: &mut VideoBus) -> u8 {
         match addr {
             //Write-Only
             0 => {
@@ -79,12 +129,13 @@ impl NESPpu {
             2 => {
                 //This one is interesting, since only the top 3 bits
                 //actually contain the status register
-                
-                if self.cycles == -1 {
-                    self.status &= 0x7F;
-                }
-                
-                self.status&0xE0 | self.scroll&0x1F
+                let status = self.status&0xE0 | self.scroll&0x1F;
+
+                //Reading the status clears the vblank flag and the write toggle
+                self.status &= 0x7F;
+                self.write_toggle = false;
+
+                status
             }
             
             //Write-Only
@@ -109,7 +160,22 @@ impl NESPpu {
             
             //Read-Write
             7 => {
-                self.data
+                let addr = self.vram_addr;
+
+                //Palette reads come straight back, but the buffer
+                //still gets the nametable byte underneath
+                let val = if addr >= 0x3F00 {
+                    self.read_buffer = bus.read(addr - 0x1000);
+                    self.palette[NESPpu::palette_index(addr)]
+                } else {
+                    let val = self.read_buffer;
+                    self.read_buffer = bus.read(addr);
+                    val
+                };
+
+                self.increment_vram_addr();
+
+                val
             }
 
             //If the interconnect is programmed properly
@@ -125,7 +191,7 @@ impl NESPpu {
 
     //Info on what address maps to what
     //https://wiki.nesdev.com/w/index.php/PPU_registers
-    pub fn write_ppu(&mut self, addr: usize, val: u8){
+    pub fn write_ppu(&mut self, addr: usize, val: u8, bus: &mut VideoBus){
         match addr {
             //Write-Only
             0 => {
@@ -160,17 +226,32 @@ impl NESPpu {
             
             //Write-Only
             5 => {
-                
+                self.scroll = val;
+                self.write_toggle = !self.write_toggle;
             }
             
-            //Write-Only
+            //Write-Only, high byte first
             6 => {
-                
+                if !self.write_toggle {
+                    self.vram_addr = ((val as u16 & 0x3F) << 8) | (self.vram_addr & 0x00FF);
+                } else {
+                    self.vram_addr = (self.vram_addr & 0xFF00) | val as u16;
+                }
+
+                self.write_toggle = !self.write_toggle;
             }
             
             //Read-Write
             7 => {
-                
+                let addr = self.vram_addr;
+
+                if addr >= 0x3F00 {
+                    self.palette[NESPpu::palette_index(addr)] = val;
+                } else {
+                    bus.write(addr, val);
+                }
+
+                self.increment_vram_addr();
             }
 
             //If the interconnect is programmed properly
@@ -181,29 +262,122 @@ impl NESPpu {
         }
     }
 
-    pub fn do_cycle(&mut self, pt0: &[u8], pt1: &[u8], nt0: &[u8], nt1: &[u8], window: &mut Window) {
-        for y in 0..HEIGHT {
-            for x in 0..WIDTH {
-                //self.vram[x + (y*WIDTH)] = (((x ^ y) & 0xff) * 1) as u32;
+    //Advances the PPU by a single dot, returns true when a frame
+    //has finished and is ready to be drawn
+    pub fn step(&mut self, bus: &mut VideoBus) -> bool {
+        let mut frame_done = false;
 
-                let nt = if self.ctrl&0x3 == 0 || self.ctrl&0x3 == 1 {
-                    nt0
-                } else {
-                    nt1
-                };
-                let pt = if self.ctrl&0x8 == 0 {
-                    pt0
-                } else {
-                    pt1
-                };
+        //The fetches, each group of them batched up at a single dot.
+        //Visible lines go black while rendering is off
+        if self.scanline < 240 {
+            match (self.dot, self.rendering()) {
+                (1, true) => self.render_scanline(bus),
+                (260, true) => self.fetch_sprites(bus),
+                (336, true) => self.prefetch_tiles(bus),
 
-                let pattern_addr = nt[x/32 + ((y/30)*32)] as usize;
-                
-                let sliver1 = pt[pattern_addr*16 + (y%8)];
-                let sliver2 = pt[pattern_addr*16 + (y%8) + 8];
+                (1, false) if self.scanline >= 0 => {
+                    let y = self.scanline as usize;
+                    for pixel in &mut self.vram[(y*WIDTH)..((y+1)*WIDTH)] {
+                        *pixel = 0;
+                    }
+                }
+
+                _ => {}
+            }
+        }
 
-                let color1 = (sliver1 >> (7-(x%8))) & 0x1;
-                let color2 = (sliver2 >> (7-(x%8))) & 0x1;
+        match (self.scanline, self.dot) {
+            (-1, 1) => {
+                self.status &= 0x1F;
+            }
+
+            (240, 0) => {
+                frame_done = true;
+            }
+
+            (241, 1) => {
+                self.status |= 0x80;
+            }
+
+            _ => {
+
+            }
+        }
+
+        self.dot += 1;
+
+        //The pre-render line is one dot shorter on odd frames while rendering
+        if self.scanline == -1 && self.dot == 340 && self.odd_frame && self.rendering() {
+            self.dot = 341;
+        }
+
+        if self.dot > 340 {
+            self.dot = 0;
+            self.scanline += 1;
+
+            if self.scanline > 260 {
+                self.scanline = -1;
+                self.odd_frame = !self.odd_frame;
+            }
+        }
+
+        frame_done
+    }
+
+    //Address of the tile in a column of the current nametable, on row y.
+    //Columns past the right edge are in the nametable next door
+    fn tile_addr(&self, column: usize, y: usize) -> u16 {
+        let nametable = 0x2000 + 0x400 * (self.ctrl&0x3) as u16;
+        let nametable = if column >= 32 { nametable ^ 0x400 } else { nametable };
+
+        nametable + ((column % 32) + (y/8)*32) as u16
+    }
+
+    //Background tile for a column of the current nametable, as the two
+    //pattern bytes for row y. The tile, its attribute byte and the two
+    //pattern bytes are all read, in the same order as the real thing,
+    //since that's what boards watching the bus count on
+    fn fetch_tile(&self, bus: &mut VideoBus, column: usize, y: usize) -> (u8, u8) {
+        let pattern_table = if self.ctrl&0x10 == 0 { 0x0000 } else { 0x1000 };
+
+        let tile_addr = self.tile_addr(column, y);
+        let tile = bus.read(tile_addr) as u16;
+
+        //Not used until the palette is
+        let attribute_addr = (tile_addr & 0x2C00) | 0x3C0 | ((tile_addr >> 4) & 0x38) | ((tile_addr >> 2) & 0x7);
+        bus.read(attribute_addr);
+
+        let sliver1 = bus.fetch(pattern_table + tile*16 + (y%8) as u16);
+        let sliver2 = bus.fetch(pattern_table + tile*16 + (y%8) as u16 + 8);
+
+        (sliver1, sliver2)
+    }
+
+    //Draws the background of the current scanline in three debug colours
+    //until the palette is used. The first two tiles were fetched at the end
+    //of the line before, the rest are fetched left to right like the real
+    //thing, including two past the right edge that never get drawn.
+    //The pre-render line fetches the same way, but draws nothing
+    //https://wiki.nesdev.com/w/index.php/PPU_rendering#Cycles_1-256
+    fn render_scanline(&mut self, bus: &mut VideoBus) {
+        let y = self.scanline.max(0) as usize;
+
+        for column in 0..34 {
+            let (sliver1, sliver2) = if column < 2 {
+                self.prefetch[column]
+            } else {
+                self.fetch_tile(bus, column, y)
+            };
+
+            if self.scanline < 0 || column >= 32 {
+                continue;
+            }
+
+            for bit in 0..8 {
+                let x = column*8 + bit;
+
+                let color1 = (sliver1 >> (7-bit)) & 0x1;
+                let color2 = (sliver2 >> (7-bit)) & 0x1;
 
                 if color1 > 0 && color2 > 0 {
                     self.vram[x + (y*WIDTH)] = 0xFF0000;
@@ -217,33 +391,46 @@ impl NESPpu {
                 else {
                     self.vram[x + (y*WIDTH)] = 0;
                 }
-
-                //self.vram[x + (y*WIDTH)] = ((colorr as u32) << 16) 
-                //    | ((colorg as u32) << 16)
-                //    | (colorb as u32);
             }
         }
-        
-        self.cycles += 1;
+    }
 
-        match self.cycles {
-            -1 => {
-                self.status &= 0x7F;
-            }
+    //No sprites are drawn yet, but the PPU fetches patterns for eight of them
+    //on every line whether they're there or not, each after two garbage
+    //nametable reads. Empty slots fetch tile $FF, which is in the $1000
+    //pattern table for 8x16 sprites
+    //https://wiki.nesdev.com/w/index.php/PPU_rendering#Cycles_257-320
+    fn fetch_sprites(&mut self, bus: &mut VideoBus) {
+        let tile_addr = self.tile_addr(0, self.scanline.max(0) as usize);
 
-            241 => {
-                self.status |= 0x80;
-            }
+        let addr = if self.ctrl&0x20 > 0 {
+            0x1000 + 0xFE*16
+        } else if self.ctrl&0x08 > 0 {
+            0x1000 + 0xFF*16
+        } else {
+            0xFF*16
+        };
 
-            340 => {
-                self.cycles = -1;
-            }
+        for _ in 0..8 {
+            bus.read(tile_addr);
+            bus.read(tile_addr);
+            bus.fetch(addr);
+            bus.fetch(addr + 8);
+        }
+    }
 
-            _ => {
+    //The first two tiles of the next line, then two reads of the third tile's
+    //nametable byte that nothing uses (the MMC5 spots new lines by them)
+    //https://wiki.nesdev.com/w/index.php/PPU_rendering#Cycles_321-336
+    fn prefetch_tiles(&mut self, bus: &mut VideoBus) {
+        let y = ((self.scanline + 1) % 240) as usize;
 
-            }
+        for column in 0..2 {
+            self.prefetch[column] = self.fetch_tile(bus, column, y);
         }
 
-        window.update_with_buffer(&self.vram);
+        let tile_addr = self.tile_addr(2, y);
+        bus.read(tile_addr);
+        bus.read(tile_addr);
     }
-}
\ No newline at end of file
+}
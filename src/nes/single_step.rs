@@ -0,0 +1,241 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use super::cpu::*;
+use super::bus::*;
+use super::json::Json;
+
+//Runs the per-opcode JSON CPU tests (one file per opcode, like 6d.json) from a directory.
+//Each case is one instruction with the registers and RAM before and after, and every
+//bus cycle in between. Returns true if every case passed
+//https://github.com/SingleStepTests/65x02
+pub fn run(dir: &str, variant: CpuVariant) -> bool {
+    let mut files: Vec<_> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+            .collect(),
+        Err(why) => {
+            println!("couldn't read test directory {}: {}", dir, why);
+            return false;
+        }
+    };
+    files.sort();
+
+    let mut failed_opcodes = 0;
+
+    for path in &files {
+        if !run_file(path, variant) {
+            failed_opcodes += 1;
+        }
+    }
+
+    println!("{} of {} opcodes passed", files.len() - failed_opcodes, files.len());
+
+    failed_opcodes == 0
+}
+
+//Runs every case in one file and prints a line for the opcode
+fn run_file(path: &Path, variant: CpuVariant) -> bool {
+    let name = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+
+    let mut text = String::new();
+    if let Err(why) = File::open(path).and_then(|mut file| file.read_to_string(&mut text)) {
+        println!("{}: couldn't read {}: {}", name, path.display(), why);
+        return false;
+    }
+
+    let json = match Json::parse(&text) {
+        Ok(json) => json,
+        Err(why) => {
+            println!("{}: couldn't parse {}: {}", name, path.display(), why);
+            return false;
+        }
+    };
+
+    let cases = match json.as_array() {
+        Some(cases) => cases,
+        None => {
+            println!("{}: expected an array of test cases", name);
+            return false;
+        }
+    };
+
+    let mut bus = FlatBus::new();
+    let mut failed = 0;
+    let mut first_failure = None;
+
+    for case in cases {
+        if let Err(why) = run_case(&mut bus, case, variant) {
+            failed += 1;
+
+            if first_failure.is_none() {
+                let case_name = case.get("name").and_then(Json::as_str).unwrap_or("?");
+                first_failure = Some(format!("\"{}\": {}", case_name, why));
+            }
+        }
+    }
+
+    match first_failure {
+        None => println!("{}: {} passed", name, cases.len()),
+        Some(failure) => println!("{}: {} of {} failed, first was {}", name, failed, cases.len(), failure),
+    }
+
+    failed == 0
+}
+
+//Registers and RAM from a test case's "initial" or "final" object
+struct State {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+fn field(json: &Json, key: &str) -> Result<u64, String> {
+    json.get(key).and_then(Json::as_u64).ok_or(format!("missing \"{}\"", key))
+}
+
+fn parse_state(json: Option<&Json>) -> Result<State, String> {
+    let json = json.ok_or("missing state".to_string())?;
+
+    let mut ram = Vec::new();
+    for cell in json.get("ram").and_then(Json::as_array).unwrap_or(&[]) {
+        match cell.as_array() {
+            Some(&[ref addr, ref val]) => match (addr.as_u64(), val.as_u64()) {
+                (Some(addr), Some(val)) => ram.push((addr as u16, val as u8)),
+                _ => return Err("bad ram entry".to_string()),
+            },
+            _ => return Err("bad ram entry".to_string()),
+        }
+    }
+
+    Ok(State {
+        pc: field(json, "pc")? as u16,
+        s: field(json, "s")? as u8,
+        a: field(json, "a")? as u8,
+        x: field(json, "x")? as u8,
+        y: field(json, "y")? as u8,
+        p: field(json, "p")? as u8,
+        ram: ram,
+    })
+}
+
+fn parse_cycles(json: Option<&Json>) -> Result<Vec<BusCycle>, String> {
+    let mut cycles = Vec::new();
+
+    for cycle in json.and_then(Json::as_array).ok_or("missing cycles".to_string())? {
+        match cycle.as_array() {
+            Some(&[ref addr, ref val, ref kind]) => match (addr.as_u64(), val.as_u64(), kind.as_str()) {
+                (Some(addr), Some(val), Some(kind)) => cycles.push(BusCycle {
+                    addr: addr as u16,
+                    val: val as u8,
+                    write: kind == "write",
+                }),
+                _ => return Err("bad cycle entry".to_string()),
+            },
+            _ => return Err("bad cycle entry".to_string()),
+        }
+    }
+
+    Ok(cycles)
+}
+
+fn describe(cycle: &BusCycle) -> String {
+    format!("{} {:04X} {:02X}", if cycle.write { "write" } else { "read" }, cycle.addr, cycle.val)
+}
+
+//Sets up the cpu and RAM, runs one instruction cycle by cycle and checks everything it did
+fn run_case(bus: &mut FlatBus, case: &Json, variant: CpuVariant) -> Result<(), String> {
+    let initial = parse_state(case.get("initial"))?;
+    let expected = parse_state(case.get("final"))?;
+    let expected_cycles = parse_cycles(case.get("cycles"))?;
+
+    //RAM is shared between cases, but every case lists everything it reads
+    for &(addr, val) in &initial.ram {
+        bus.ram[addr as usize] = val;
+    }
+
+    let mut cpu = NESCpu::with_mode(ExecutionMode::CycleStepped);
+    cpu.set_variant(variant);
+    cpu.set_pc(initial.pc);
+    cpu.set_s(initial.s);
+    cpu.set_a(initial.a);
+    cpu.set_x(initial.x);
+    cpu.set_y(initial.y);
+    cpu.set_p_u8(initial.p);
+
+    bus.log = Some(Vec::new());
+    let result = cpu.do_instruction(bus);
+    let cycles = bus.log.take().unwrap_or(Vec::new());
+
+    if let Err(err) = result {
+        return Err(err.to_string())
+    }
+
+    let registers = [
+        ("pc", cpu.pc() as u64, expected.pc as u64),
+        ("s", cpu.s() as u64, expected.s as u64),
+        ("a", cpu.a() as u64, expected.a as u64),
+        ("x", cpu.x() as u64, expected.x as u64),
+        ("y", cpu.y() as u64, expected.y as u64),
+        ("p", cpu.p().to_u8() as u64, expected.p as u64),
+    ];
+
+    for &(name, got, wanted) in &registers {
+        if got != wanted {
+            return Err(format!("{} was {:X}, expected {:X}", name, got, wanted))
+        }
+    }
+
+    for &(addr, val) in &expected.ram {
+        if bus.ram[addr as usize] != val {
+            return Err(format!("${:04X} was {:02X}, expected {:02X}", addr, bus.ram[addr as usize], val))
+        }
+    }
+
+    for i in 0..cycles.len().max(expected_cycles.len()) {
+        match (cycles.get(i), expected_cycles.get(i)) {
+            (Some(got), Some(wanted)) if got == wanted => {}
+            (Some(got), Some(wanted)) => {
+                return Err(format!("cycle {} was {}, expected {}", i + 1, describe(got), describe(wanted)))
+            }
+            (Some(got), None) => return Err(format!("extra cycle {}: {}", i + 1, describe(got))),
+            (None, Some(wanted)) => return Err(format!("missing cycle {}: {}", i + 1, describe(wanted))),
+            (None, None) => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //LDA #$42, written out the way the test suites have it
+    const CASE: &'static str = r#"{
+        "name": "a9 42 00",
+        "initial": { "pc": 512, "s": 253, "a": 0, "x": 0, "y": 0, "p": 38, "ram": [[512, 169], [513, 66]] },
+        "final": { "pc": 514, "s": 253, "a": 66, "x": 0, "y": 0, "p": 36, "ram": [[512, 169], [513, 66]] },
+        "cycles": [[512, 169, "read"], [513, 66, "read"]]
+    }"#;
+
+    #[test]
+    fn runs_a_case() {
+        let case = Json::parse(CASE).unwrap();
+
+        assert_eq!(run_case(&mut FlatBus::new(), &case, CpuVariant::Nmos6502), Ok(()));
+    }
+
+    #[test]
+    fn reports_a_wrong_register() {
+        let case = Json::parse(&CASE.replace("\"a\": 66", "\"a\": 67")).unwrap();
+
+        assert_eq!(run_case(&mut FlatBus::new(), &case, CpuVariant::Nmos6502), Err("a was 42, expected 43".to_string()));
+    }
+}
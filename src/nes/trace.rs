@@ -0,0 +1,148 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write, BufWriter};
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use super::opcode::Opcode;
+
+//The state of the machine just before an instruction runs
+#[derive(Copy, Clone, Debug)]
+pub struct TraceRecord {
+    pub pc: u16,
+    pub opcode: Opcode,
+
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub s: u8,
+    pub p: u8,
+
+    pub cycle: u64,
+
+    pub scanline: i16,
+    pub dot: u16,
+}
+
+impl TraceRecord {
+    //Only the bytes that belong to the instruction
+    pub fn bytes(&self) -> Vec<u8> {
+        let bytes = [self.opcode.op(), self.opcode.imm1(), self.opcode.imm2()];
+        bytes[..self.opcode.info().len as usize].to_vec()
+    }
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes().iter().map(|b| format!("{:02X}", b)).collect();
+
+        write!(f, "{:04X}  {:<8}  {}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} SL:{} DOT:{} CYC:{}",
+               self.pc, bytes.join(" "), self.opcode.info().mnemonic,
+               self.a, self.x, self.y, self.p, self.s,
+               self.scanline, self.dot, self.cycle)
+    }
+}
+
+//Anything that wants to see every instruction the cpu runs
+pub trait TraceSink {
+    fn trace(&mut self, record: &TraceRecord);
+}
+
+//Any closure works as a sink
+impl<F> TraceSink for F where F: FnMut(&TraceRecord) {
+    fn trace(&mut self, record: &TraceRecord) {
+        self(record)
+    }
+}
+
+//Writes one line per instruction to a file
+pub struct FileTrace {
+    writer: BufWriter<File>,
+}
+
+impl FileTrace {
+    pub fn new(path: &str) -> io::Result<FileTrace> {
+        Ok(FileTrace {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+}
+
+impl TraceSink for FileTrace {
+    fn trace(&mut self, record: &TraceRecord) {
+        let _ = writeln!(self.writer, "{}", record);
+    }
+}
+
+//Keeps the last few instructions around, for looking back at what led up to a crash.
+//Clones share the same buffer, so keep one to read back from after handing one to the cpu
+#[derive(Clone)]
+pub struct RingBufferTrace {
+    records: Rc<RefCell<VecDeque<TraceRecord>>>,
+    capacity: usize,
+}
+
+impl RingBufferTrace {
+    //A capacity of 0 keeps nothing
+    pub fn new(capacity: usize) -> RingBufferTrace {
+        RingBufferTrace {
+            records: Rc::new(RefCell::new(VecDeque::with_capacity(capacity))),
+            capacity: capacity,
+        }
+    }
+
+    //Oldest first
+    pub fn records(&self) -> Vec<TraceRecord> {
+        self.records.borrow().iter().cloned().collect()
+    }
+}
+
+impl TraceSink for RingBufferTrace {
+    fn trace(&mut self, record: &TraceRecord) {
+        if self.capacity == 0 {
+            return
+        }
+
+        let mut records = self.records.borrow_mut();
+
+        while records.len() >= self.capacity {
+            records.pop_front();
+        }
+
+        records.push_back(*record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::opcode::Opcode;
+
+    fn record(pc: u16) -> TraceRecord {
+        TraceRecord { pc: pc, opcode: Opcode::new(0xEA), a: 0, x: 0, y: 0, s: 0xFD, p: 0x24, cycle: 0, scanline: 0, dot: 0 }
+    }
+
+    #[test]
+    fn ring_buffer_keeps_the_newest_records() {
+        let history = RingBufferTrace::new(2);
+        let mut sink = history.clone();
+
+        for pc in 0..5 {
+            sink.trace(&record(pc));
+        }
+
+        let pcs: Vec<u16> = history.records().iter().map(|record| record.pc).collect();
+        assert_eq!(pcs, vec![3, 4]);
+    }
+
+    #[test]
+    fn ring_buffer_with_no_capacity_keeps_nothing() {
+        let history = RingBufferTrace::new(0);
+        let mut sink = history.clone();
+
+        sink.trace(&record(0));
+
+        assert!(history.records().is_empty());
+    }
+}